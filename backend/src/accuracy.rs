@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    location::Coordinates,
+    metrics::TimedSend,
+    store,
+    weather::{PublicWeatherResponse, coord_key},
+};
+
+/// Lead times (hours ahead) whose predictions are kept for verification.
+const LEAD_HOURS: [i64; 6] = [1, 3, 6, 12, 24, 48];
+/// Precipitation at or above this counts as "it rained" for the hit rate.
+const WET_HOUR_MM: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+struct Prediction {
+    target_time: i64,
+    air_temperature: Option<f64>,
+    precipitation_amount: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+pub struct Observation {
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub air_temperature: Option<f64>,
+    /// Precipitation over the hour, in mm
+    #[serde(default)]
+    pub precipitation_amount: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct LeadTimeAccuracy {
+    pub lead_hours: i64,
+    pub samples: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_mae: Option<f64>,
+    /// Forecast minus observed; positive means the forecast ran warm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_bias: Option<f64>,
+    /// Share of hours where the forecast got "wet or dry" right
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation_hit_rate: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ProviderAccuracy {
+    pub provider: String,
+    pub lead_times: Vec<LeadTimeAccuracy>,
+}
+
+/// What the forecasts were compared against.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TruthSource {
+    /// Readings posted from a local sensor
+    Observations,
+    /// met.no's own "now" values, which flatter met.no's forecasts
+    Forecast,
+    /// Sensor readings for some hours, met.no's "now" values for the rest
+    Mixed,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct AccuracyReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Absent when no hours could be verified yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truth_source: Option<TruthSource>,
+    pub providers: Vec<ProviderAccuracy>,
+}
+
+fn hour_start(timestamp: i64) -> i64 {
+    timestamp / 3600 * 3600
+}
+
+fn metno_predictions(weather: &PublicWeatherResponse) -> Vec<Prediction> {
+    weather
+        .properties
+        .timeseries
+        .iter()
+        .filter_map(|entry| {
+            let time = DateTime::parse_from_rfc3339(&entry.time).ok()?;
+            Some(Prediction {
+                target_time: time.timestamp(),
+                air_temperature: entry
+                    .data
+                    .instant
+                    .details
+                    .as_ref()
+                    .and_then(|d| d.air_temperature),
+                precipitation_amount: entry
+                    .data
+                    .next_1_hours
+                    .as_ref()
+                    .and_then(|f| f.details.as_ref())
+                    .and_then(|d| d.precipitation_amount),
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct OpenMeteoForecast {
+    hourly: OpenMeteoHourly,
+}
+
+#[derive(Deserialize)]
+struct OpenMeteoHourly {
+    time: Vec<String>,
+    temperature_2m: Vec<Option<f64>>,
+    precipitation: Vec<Option<f64>>,
+}
+
+async fn open_meteo_predictions(
+    coord: &Coordinates,
+) -> Result<Vec<Prediction>, Box<dyn std::error::Error + Send + Sync>> {
+    let url = format!(
+        "https://api.open-meteo.com/v1/forecast?latitude={}&longitude={}&hourly=temperature_2m,precipitation&timezone=GMT&forecast_days=3",
+        coord.lat(),
+        coord.lon()
+    );
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&url)
+        .send_timed()
+        .await?
+        .error_for_status()?
+        .json::<OpenMeteoForecast>()
+        .await?;
+
+    let hourly = response.hourly;
+    Ok(hourly
+        .time
+        .iter()
+        .enumerate()
+        .filter_map(|(i, time)| {
+            // Open-Meteo precipitation is the sum over the preceding hour
+            let end = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+                .ok()?
+                .and_utc();
+            Some(Prediction {
+                target_time: end.timestamp(),
+                air_temperature: hourly.temperature_2m.get(i).copied().flatten(),
+                precipitation_amount: hourly.precipitation.get(i + 1).copied().flatten(),
+            })
+        })
+        .collect())
+}
+
+fn comparison_providers() -> Vec<String> {
+    std::env::var("ACCURACY_PROVIDERS")
+        .map(|providers| {
+            providers
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Problems with `ACCURACY_PROVIDERS`, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    comparison_providers()
+        .into_iter()
+        .filter(|provider| provider != "open-meteo")
+        .map(|provider| format!("Unknown accuracy provider: {}", provider))
+        .collect()
+}
+
+async fn store_predictions(
+    coord: &Coordinates,
+    provider: &'static str,
+    predictions: Vec<Prediction>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    let issued_at = Utc::now().timestamp();
+    let current_hour = hour_start(issued_at);
+
+    store::with_db(move |conn| {
+        let tx = conn.transaction()?;
+        for prediction in predictions {
+            let lead = (prediction.target_time - current_hour) / 3600;
+            if !LEAD_HOURS.contains(&lead) {
+                continue;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO forecast_predictions (
+                    coord_key, provider, target_time, lead_hours, issued_at,
+                    air_temperature, precipitation_amount
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    key,
+                    provider,
+                    prediction.target_time,
+                    lead,
+                    issued_at,
+                    prediction.air_temperature,
+                    prediction.precipitation_amount,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await
+}
+
+/// Keeps the predictions of this forecast (and any comparison providers) for
+/// the lead times we verify.
+pub async fn record_predictions(
+    coord: &Coordinates,
+    weather: &PublicWeatherResponse,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    store_predictions(coord, "met.no", metno_predictions(weather)).await?;
+
+    for provider in comparison_providers() {
+        match provider.as_str() {
+            "open-meteo" => match open_meteo_predictions(coord).await {
+                Ok(predictions) => store_predictions(coord, "open-meteo", predictions).await?,
+                Err(e) => tracing::warn!("Failed to fetch Open-Meteo forecast: {}", e),
+            },
+            other => tracing::warn!("Unknown accuracy provider: {}", other),
+        }
+    }
+    Ok(())
+}
+
+/// Stores a reading from a local sensor. It's preferred over the forecast's
+/// own "now" values when verifying that hour.
+pub async fn record_observation(
+    coord: &Coordinates,
+    observation: Observation,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    let hour = hour_start(observation.time.unwrap_or_else(Utc::now).timestamp());
+
+    store::with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO observations (coord_key, observed_at, air_temperature, precipitation_amount)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (coord_key, observed_at) DO UPDATE SET
+                air_temperature = COALESCE(excluded.air_temperature, air_temperature),
+                precipitation_amount = COALESCE(excluded.precipitation_amount, precipitation_amount)",
+            params![
+                key,
+                hour,
+                observation.air_temperature,
+                observation.precipitation_amount,
+            ],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TruthHour {
+    air_temperature: Option<f64>,
+    precipitation_amount: Option<f64>,
+    observed: bool,
+}
+
+type Truth = HashMap<i64, TruthHour>;
+
+// Observed temperature and precipitation per hour: sensor readings where we
+// have them, otherwise the recorded "now" conditions
+fn load_truth(
+    conn: &rusqlite::Connection,
+    key: &str,
+    from: i64,
+    to: i64,
+) -> rusqlite::Result<Truth> {
+    let mut truth = Truth::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT recorded_at / 3600 * 3600 AS hour, AVG(air_temperature), AVG(precipitation_amount)
+         FROM weather_history
+         WHERE coord_key = ?1 AND recorded_at >= ?2 AND recorded_at < ?3
+         GROUP BY hour",
+    )?;
+    for row in stmt.query_map(params![key, from, to], |row| {
+        Ok((
+            row.get(0)?,
+            TruthHour {
+                air_temperature: row.get(1)?,
+                precipitation_amount: row.get(2)?,
+                observed: false,
+            },
+        ))
+    })? {
+        let (hour, values) = row?;
+        truth.insert(hour, values);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT observed_at, air_temperature, precipitation_amount
+         FROM observations
+         WHERE coord_key = ?1 AND observed_at >= ?2 AND observed_at < ?3",
+    )?;
+    for row in stmt.query_map(params![key, from, to], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<f64>>(1)?,
+            row.get::<_, Option<f64>>(2)?,
+        ))
+    })? {
+        let (hour, temperature, precipitation) = row?;
+        let entry = truth.entry(hour).or_default();
+        entry.air_temperature = temperature.or(entry.air_temperature);
+        entry.precipitation_amount = precipitation.or(entry.precipitation_amount);
+        entry.observed = true;
+    }
+
+    Ok(truth)
+}
+
+#[derive(Default)]
+struct Errors {
+    samples: usize,
+    temperature: Vec<f64>,
+    precipitation_hits: Vec<bool>,
+}
+
+fn lead_time_accuracy(lead_hours: i64, errors: &Errors) -> LeadTimeAccuracy {
+    let n = errors.temperature.len() as f64;
+    let hits = &errors.precipitation_hits;
+    LeadTimeAccuracy {
+        lead_hours,
+        samples: errors.samples,
+        temperature_mae: (n > 0.0)
+            .then(|| errors.temperature.iter().map(|e| e.abs()).sum::<f64>() / n),
+        temperature_bias: (n > 0.0).then(|| errors.temperature.iter().sum::<f64>() / n),
+        precipitation_hit_rate: (!hits.is_empty())
+            .then(|| hits.iter().filter(|hit| **hit).count() as f64 / hits.len() as f64),
+    }
+}
+
+pub async fn accuracy_for(
+    coord: &Coordinates,
+    days: i64,
+) -> Result<AccuracyReport, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    let to = Utc::now();
+    let from = to - Duration::days(days);
+    let (from_ts, to_ts) = (from.timestamp(), hour_start(to.timestamp()));
+
+    let (errors, sources) = store::with_db(move |conn| {
+        let truth = load_truth(conn, &key, from_ts, to_ts + 3600)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT provider, lead_hours, target_time, air_temperature, precipitation_amount
+             FROM forecast_predictions
+             WHERE coord_key = ?1 AND target_time >= ?2 AND target_time <= ?3",
+        )?;
+        let rows = stmt.query_map(params![key, from_ts, to_ts], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+                row.get::<_, Option<f64>>(4)?,
+            ))
+        })?;
+
+        let mut errors: BTreeMap<(String, i64), Errors> = BTreeMap::new();
+        // Whether any verified hour used sensor readings, and whether any did not
+        let mut sources = (false, false);
+        for row in rows {
+            let (provider, lead, target, temperature, precipitation) = row?;
+            let Some(observed) = truth.get(&target) else {
+                continue;
+            };
+            if observed.observed {
+                sources.0 = true;
+            } else {
+                sources.1 = true;
+            }
+            let entry = errors.entry((provider, lead)).or_default();
+            entry.samples += 1;
+            if let (Some(predicted), Some(actual)) = (temperature, observed.air_temperature) {
+                entry.temperature.push(predicted - actual);
+            }
+            if let (Some(predicted), Some(actual)) = (precipitation, observed.precipitation_amount)
+            {
+                entry
+                    .precipitation_hits
+                    .push((predicted >= WET_HOUR_MM) == (actual >= WET_HOUR_MM));
+            }
+        }
+        Ok((errors, sources))
+    })
+    .await?;
+
+    let truth_source = match sources {
+        (true, true) => Some(TruthSource::Mixed),
+        (true, false) => Some(TruthSource::Observations),
+        (false, true) => Some(TruthSource::Forecast),
+        (false, false) => None,
+    };
+
+    let mut providers: Vec<ProviderAccuracy> = Vec::new();
+    for ((provider, lead), errors) in errors {
+        let accuracy = lead_time_accuracy(lead, &errors);
+        match providers.iter_mut().find(|p| p.provider == provider) {
+            Some(existing) => existing.lead_times.push(accuracy),
+            None => providers.push(ProviderAccuracy {
+                provider,
+                lead_times: vec![accuracy],
+            }),
+        }
+    }
+
+    Ok(AccuracyReport {
+        from,
+        to,
+        truth_source,
+        providers,
+    })
+}
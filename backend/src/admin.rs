@@ -0,0 +1,194 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::{self, Scope},
+    location::{self, Coordinates, LocationCache},
+    prefetch, store,
+    weather::{self, WeatherResponse},
+};
+
+/// The caches the admin API can inspect and edit.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminCache {
+    Location,
+    Weather,
+    Summary,
+}
+
+impl AdminCache {
+    fn namespace(self) -> &'static str {
+        match self {
+            AdminCache::Location => location::LOCATION_NAMESPACE,
+            AdminCache::Weather => weather::WEATHER_NAMESPACE,
+            AdminCache::Summary => weather::WEATHER_SUMMARY_NAMESPACE,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SummaryOverride {
+    summary: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshQuery {
+    /// Also forget the cached geocode and look the name up again
+    #[serde(default)]
+    geocode: bool,
+    timezone: Option<String>,
+}
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn deleted_status(removed: bool) -> StatusCode {
+    if removed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+pub async fn handle_list_cache(
+    Path(cache): Path<AdminCache>,
+) -> Result<Json<Vec<store::EntryInfo>>, (StatusCode, String)> {
+    store::entries(cache.namespace())
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+pub async fn handle_get_cache_entry(
+    Path((cache, key)): Path<(AdminCache, String)>,
+) -> Result<Json<store::EntryInfo>, (StatusCode, String)> {
+    match store::entry(cache.namespace(), &key).await {
+        Ok(Some(entry)) => Ok(Json(entry)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("No {:?} entry {}", cache, key),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Replaces a cache entry. The body is what the cache holds: coordinates, a
+/// met.no forecast, or `{"summary": ...}`.
+pub async fn handle_put_cache_entry(
+    Path((cache, key)): Path<(AdminCache, String)>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let invalid = |e: serde_json::Error| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string());
+    let result = match cache {
+        AdminCache::Location => {
+            let coords: Coordinates = serde_json::from_value(value).map_err(invalid)?;
+            location::set_cached(&key, coords).await
+        }
+        AdminCache::Weather => {
+            let forecast: WeatherResponse = serde_json::from_value(value).map_err(invalid)?;
+            weather::set_cached_weather(&key, forecast).await
+        }
+        AdminCache::Summary => {
+            let body: SummaryOverride = serde_json::from_value(value).map_err(invalid)?;
+            weather::set_cached_summary(&key, body.summary).await
+        }
+    };
+    result.map_err(internal_error)?;
+    tracing::info!(cache = ?cache, key = %key, "Cache entry replaced by admin");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn handle_delete_cache_entry(
+    Path((cache, key)): Path<(AdminCache, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let removed = match cache {
+        AdminCache::Location => location::forget(&key).await,
+        AdminCache::Weather => weather::forget_weather(&key).await,
+        AdminCache::Summary => weather::forget_summary(&key).await,
+    }
+    .map_err(internal_error)?;
+    tracing::info!(cache = ?cache, key = %key, "Cache entry deleted by admin");
+    Ok(deleted_status(removed))
+}
+
+/// Refetches the forecast, alerts and summary of a location right away.
+#[tracing::instrument(skip_all, fields(location = %location))]
+pub async fn handle_refresh_location(
+    Path(location): Path<String>,
+    Query(query): Query<RefreshQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if query.geocode {
+        location::forget(&location).await.map_err(internal_error)?;
+    }
+    prefetch::refresh(&location, query.timezone)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn handle_list_overrides() -> Result<Json<LocationCache>, (StatusCode, String)> {
+    location::overrides()
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Pins a location name to fixed coordinates, for places the geocoder gets wrong.
+pub async fn handle_put_override(
+    Path(location): Path<String>,
+    Json(coords): Json<Coordinates>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    location::pin(&location, coords)
+        .await
+        .map_err(internal_error)?;
+    tracing::info!(location = %location, "Location override pinned by admin");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn handle_delete_override(
+    Path(location): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let removed = location::unpin(&location).await.map_err(internal_error)?;
+    Ok(deleted_status(removed))
+}
+
+pub async fn handle_list_users() -> Result<Json<Vec<auth::UserInfo>>, (StatusCode, String)> {
+    auth::users().await.map(Json).map_err(internal_error)
+}
+
+#[derive(Deserialize)]
+pub struct UserRequest {
+    password: String,
+    scopes: Vec<Scope>,
+}
+
+/// Creates a user or replaces their password and scopes.
+pub async fn handle_put_user(
+    Path(username): Path<String>,
+    Json(user): Json<UserRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if username.trim().is_empty() || user.password.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Username and password must not be empty".to_string(),
+        ));
+    }
+    auth::set_user(&username, &user.password, user.scopes)
+        .await
+        .map_err(internal_error)?;
+    tracing::info!(username = %username, "User saved by admin");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn handle_delete_user(
+    Path(username): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let removed = auth::delete_user(&username).await.map_err(internal_error)?;
+    Ok(deleted_status(removed))
+}
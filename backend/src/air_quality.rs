@@ -0,0 +1,408 @@
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::{
+    events::{self, EventKind},
+    location::Coordinates,
+    metrics::TimedSend,
+    store,
+    weather::coord_key,
+};
+
+pub const AIR_QUALITY_NAMESPACE: &str = "air_quality";
+pub const AIR_QUALITY_CACHE_SECONDS: i64 = 600;
+const FORECAST_HOURS: usize = 24;
+
+static AIR_QUALITY_CACHE: Lazy<RwLock<AirQualityCache>> = Lazy::new(|| {
+    RwLock::new(AirQualityCache {
+        cache: HashMap::new(),
+    })
+});
+
+type ProviderFuture<'a> = Pin<
+    Box<
+        dyn Future<Output = Result<AirQualityReport, Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + 'a,
+    >,
+>;
+
+/// A source of air quality forecasts. Providers are tried in order until one
+/// covers the requested location.
+pub trait AirQualityProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn fetch<'a>(&'a self, coord: &'a Coordinates) -> ProviderFuture<'a>;
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct PollenReading {
+    pub kind: String,
+    pub grains_per_m3: f64,
+}
+
+/// Concentrations are in µg/m³.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct AirQualitySample {
+    pub time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aqi: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pm2_5: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pm10: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no2: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub o3: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pollen: Vec<PollenReading>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct AirQualityReport {
+    pub provider: String,
+    /// The scale `aqi` is expressed in, which differs between providers
+    pub aqi_scale: String,
+    pub current: AirQualitySample,
+    pub forecast: Vec<AirQualitySample>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AirQualityCacheItem {
+    time: DateTime<Utc>,
+    report: AirQualityReport,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AirQualityCache {
+    cache: HashMap<String, AirQualityCacheItem>,
+}
+
+// Picks the sample for the current hour and the ones following it
+fn build_report(
+    provider: &str,
+    aqi_scale: &str,
+    mut samples: Vec<AirQualitySample>,
+) -> Result<AirQualityReport, Box<dyn std::error::Error + Send + Sync>> {
+    let now = Utc::now();
+    samples.sort_by_key(|s| s.time);
+    let start = samples
+        .iter()
+        .rposition(|s| s.time <= now)
+        .unwrap_or_default();
+    let mut upcoming = samples.into_iter().skip(start);
+    let current = upcoming.next().ok_or("No air quality data available")?;
+
+    Ok(AirQualityReport {
+        provider: provider.to_string(),
+        aqi_scale: aqi_scale.to_string(),
+        current,
+        forecast: upcoming.take(FORECAST_HOURS).collect(),
+    })
+}
+
+// --- met.no airqualityforecast (Norway only) ---
+
+pub struct MetNoAirQuality;
+
+#[derive(Deserialize)]
+struct MetNoAirQualityResponse {
+    data: MetNoAirQualityData,
+}
+
+#[derive(Deserialize)]
+struct MetNoAirQualityData {
+    time: Vec<MetNoAirQualityTime>,
+}
+
+#[derive(Deserialize)]
+struct MetNoAirQualityTime {
+    from: DateTime<Utc>,
+    variables: HashMap<String, MetNoAirQualityVariable>,
+}
+
+#[derive(Deserialize)]
+struct MetNoAirQualityVariable {
+    value: Option<f64>,
+}
+
+impl AirQualityProvider for MetNoAirQuality {
+    fn name(&self) -> &'static str {
+        "met.no"
+    }
+
+    fn fetch<'a>(&'a self, coord: &'a Coordinates) -> ProviderFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "https://api.met.no/weatherapi/airqualityforecast/0.1/?lat={}&lon={}",
+                coord.lat(),
+                coord.lon()
+            );
+            let client = reqwest::Client::new();
+
+            // Locations outside Norway are answered with an error status
+            let response = client
+                .get(&url)
+                .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+                .send_timed()
+                .await?
+                .error_for_status()?
+                .json::<MetNoAirQualityResponse>()
+                .await?;
+
+            let samples = response
+                .data
+                .time
+                .into_iter()
+                .map(|entry| {
+                    let value = |name: &str| entry.variables.get(name).and_then(|v| v.value);
+                    AirQualitySample {
+                        time: entry.from,
+                        aqi: value("AQI"),
+                        pm2_5: value("pm25_concentration"),
+                        pm10: value("pm10_concentration"),
+                        no2: value("no2_concentration"),
+                        o3: value("o3_concentration"),
+                        pollen: Vec::new(),
+                    }
+                })
+                .collect();
+
+            build_report(self.name(), "met.no AQI", samples)
+        })
+    }
+}
+
+// --- Open-Meteo air quality (CAMS, global with European pollen) ---
+
+pub struct OpenMeteoAirQuality;
+
+const OPEN_METEO_POLLEN: [&str; 6] = [
+    "alder_pollen",
+    "birch_pollen",
+    "grass_pollen",
+    "mugwort_pollen",
+    "olive_pollen",
+    "ragweed_pollen",
+];
+
+#[derive(Deserialize)]
+struct OpenMeteoResponse {
+    hourly: HashMap<String, serde_json::Value>,
+}
+
+impl AirQualityProvider for OpenMeteoAirQuality {
+    fn name(&self) -> &'static str {
+        "open-meteo"
+    }
+
+    fn fetch<'a>(&'a self, coord: &'a Coordinates) -> ProviderFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "https://air-quality-api.open-meteo.com/v1/air-quality?latitude={}&longitude={}&hourly=european_aqi,pm2_5,pm10,nitrogen_dioxide,ozone,{}&timezone=GMT&forecast_days=2",
+                coord.lat(),
+                coord.lon(),
+                OPEN_METEO_POLLEN.join(",")
+            );
+            let client = reqwest::Client::new();
+
+            let response = client
+                .get(&url)
+                .send_timed()
+                .await?
+                .error_for_status()?
+                .json::<OpenMeteoResponse>()
+                .await?;
+
+            let series = |name: &str| -> Vec<Option<f64>> {
+                response
+                    .hourly
+                    .get(name)
+                    .and_then(|v| v.as_array())
+                    .map(|values| values.iter().map(|v| v.as_f64()).collect())
+                    .unwrap_or_default()
+            };
+            let times: Vec<String> = response
+                .hourly
+                .get("time")
+                .and_then(|v| serde_json::from_value(v.clone()).ok())
+                .ok_or("Open-Meteo response has no hourly times")?;
+
+            let aqi = series("european_aqi");
+            let pm2_5 = series("pm2_5");
+            let pm10 = series("pm10");
+            let no2 = series("nitrogen_dioxide");
+            let o3 = series("ozone");
+            let pollen: Vec<(&str, Vec<Option<f64>>)> = OPEN_METEO_POLLEN
+                .iter()
+                .map(|name| (*name, series(name)))
+                .collect();
+
+            let samples = times
+                .iter()
+                .enumerate()
+                .filter_map(|(i, time)| {
+                    // Times come back as "2025-06-21T13:00" in GMT
+                    let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+                        .ok()?
+                        .and_utc();
+                    let at = |values: &[Option<f64>]| values.get(i).copied().flatten();
+                    Some(AirQualitySample {
+                        time,
+                        aqi: at(&aqi),
+                        pm2_5: at(&pm2_5),
+                        pm10: at(&pm10),
+                        no2: at(&no2),
+                        o3: at(&o3),
+                        pollen: pollen
+                            .iter()
+                            .filter_map(|(name, values)| {
+                                Some(PollenReading {
+                                    kind: name.trim_end_matches("_pollen").to_string(),
+                                    grains_per_m3: at(values)?,
+                                })
+                            })
+                            .collect(),
+                    })
+                })
+                .collect();
+
+            build_report(self.name(), "European AQI", samples)
+        })
+    }
+}
+
+fn provider_names() -> Vec<String> {
+    std::env::var("AIR_QUALITY_PROVIDERS")
+        .unwrap_or_else(|_| "metno,open-meteo".into())
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn provider_by_name(name: &str) -> Option<Box<dyn AirQualityProvider>> {
+    match name {
+        "metno" | "met.no" => Some(Box::new(MetNoAirQuality)),
+        "open-meteo" | "openmeteo" => Some(Box::new(OpenMeteoAirQuality)),
+        _ => None,
+    }
+}
+
+fn configured_providers() -> Vec<Box<dyn AirQualityProvider>> {
+    provider_names()
+        .iter()
+        .filter_map(|name| {
+            let provider = provider_by_name(name);
+            if provider.is_none() {
+                tracing::warn!("Unknown air quality provider: {}", name);
+            }
+            provider
+        })
+        .collect()
+}
+
+/// Problems with `AIR_QUALITY_PROVIDERS`, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    let names = provider_names();
+    let mut problems: Vec<String> = names
+        .iter()
+        .filter(|name| provider_by_name(name).is_none())
+        .map(|name| format!("Unknown air quality provider: {}", name))
+        .collect();
+    if !names.iter().any(|name| provider_by_name(name).is_some()) {
+        problems.push("No air quality providers configured".to_string());
+    }
+    problems
+}
+
+async fn load_air_quality_cache()
+-> Result<AirQualityCache, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(AirQualityCache {
+        cache: store::load_namespace(AIR_QUALITY_NAMESPACE).await?,
+    })
+}
+
+async fn save_air_quality_cache_entry(
+    key: &str,
+    entry: &AirQualityCacheItem,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let expires_at = entry.time + chrono::Duration::seconds(AIR_QUALITY_CACHE_SECONDS);
+    store::upsert(AIR_QUALITY_NAMESPACE, key, entry, Some(expires_at), None).await
+}
+
+fn is_fresh(item: &AirQualityCacheItem) -> bool {
+    Utc::now().signed_duration_since(item.time).num_seconds() < AIR_QUALITY_CACHE_SECONDS
+}
+
+/// When the cached report for `coord` was fetched, if there is one.
+pub async fn cached_at(coord: &Coordinates) -> Option<DateTime<Utc>> {
+    let cache_read = AIR_QUALITY_CACHE.read().await;
+    cache_read
+        .cache
+        .get(&coord_key(coord))
+        .map(|entry| entry.time)
+}
+
+#[tracing::instrument(skip_all, fields(coord_key = %coord_key(coord)))]
+pub async fn fetch_air_quality(
+    coord: &Coordinates,
+) -> Result<AirQualityReport, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+
+    {
+        let cache_read = AIR_QUALITY_CACHE.read().await;
+        if let Some(entry) = cache_read.cache.get(&key).filter(|e| is_fresh(e)) {
+            return Ok(entry.report.clone());
+        }
+    }
+
+    let mut cache_write = AIR_QUALITY_CACHE.write().await;
+
+    if cache_write.cache.is_empty() {
+        match load_air_quality_cache().await {
+            Ok(file_cache) => {
+                *cache_write = file_cache;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load air quality cache: {}", e);
+            }
+        }
+    }
+
+    if let Some(entry) = cache_write.cache.get(&key).filter(|e| is_fresh(e)) {
+        return Ok(entry.report.clone());
+    }
+
+    let mut last_error: Box<dyn std::error::Error + Send + Sync> =
+        "No air quality providers configured".into();
+    for provider in configured_providers() {
+        match provider.fetch(coord).await {
+            Ok(report) => {
+                let new_entry = AirQualityCacheItem {
+                    time: Utc::now(),
+                    report: report.clone(),
+                };
+                if let Err(e) = save_air_quality_cache_entry(&key, &new_entry).await {
+                    tracing::warn!("Failed to save air quality cache: {}", e);
+                }
+                cache_write.cache.insert(key, new_entry);
+                cache_write.cache.retain(|_, entry| is_fresh(entry));
+                events::publish(coord, EventKind::AirQuality, &report);
+                return Ok(report);
+            }
+            Err(e) => {
+                tracing::warn!("Air quality provider {} failed: {}", provider.name(), e);
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
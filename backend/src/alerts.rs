@@ -0,0 +1,811 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::{
+    events::{self, EventKind},
+    location::Coordinates,
+    metrics::TimedSend,
+};
+
+const METALERTS_URL: &str = "https://api.met.no/weatherapi/metalerts/2.0/current.json?lang=en";
+pub const ALERT_CACHE_SECONDS: i64 = 300;
+// After a failed refresh, what's cached is served this long before trying again
+const FAILURE_BACKOFF_SECONDS: i64 = 60;
+// Atom/RSS feeds can list hundreds of alerts; don't follow more than this per feed
+const MAX_FEED_ENTRIES: usize = 50;
+const CAP_NAMESPACE: &str = "urn:oasis:names:tc:emergency:cap:1.2";
+
+static ALERT_CACHE: Lazy<RwLock<AlertCache>> = Lazy::new(|| {
+    RwLock::new(AlertCache {
+        time: None,
+        failed_at: None,
+        alerts: Vec::new(),
+    })
+});
+static REFRESH_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+struct AlertCache {
+    time: Option<DateTime<Utc>>,
+    failed_at: Option<DateTime<Utc>>,
+    alerts: Vec<WeatherAlert>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+pub enum Severity {
+    Unknown,
+    Minor,
+    Moderate,
+    Severe,
+    Extreme,
+}
+
+impl Severity {
+    fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "extreme" => Severity::Extreme,
+            "severe" => Severity::Severe,
+            "moderate" => Severity::Moderate,
+            "minor" => Severity::Minor,
+            _ => Severity::Unknown,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub enum Certainty {
+    Observed,
+    Likely,
+    Possible,
+    Unlikely,
+    Unknown,
+}
+
+impl Certainty {
+    fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "observed" => Certainty::Observed,
+            "likely" => Certainty::Likely,
+            "possible" => Certainty::Possible,
+            "unlikely" => Certainty::Unlikely,
+            _ => Certainty::Unknown,
+        }
+    }
+}
+
+/// A single polygon in (lat, lon) pairs. The first ring is the outer boundary,
+/// any further rings are holes.
+#[derive(Debug, Clone)]
+struct AlertPolygon {
+    rings: Vec<Vec<(f64, f64)>>,
+}
+
+#[derive(Debug, Clone)]
+enum AlertArea {
+    Polygon(AlertPolygon),
+    Circle { lat: f64, lon: f64, radius_km: f64 },
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct WeatherAlert {
+    pub id: String,
+    pub source: String,
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instruction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub area_description: Option<String>,
+    pub severity: Severity,
+    pub certainty: Certainty,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub onset: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    areas: Vec<AlertArea>,
+}
+
+impl WeatherAlert {
+    fn covers(&self, coord: &Coordinates) -> bool {
+        self.areas.iter().any(|area| match area {
+            AlertArea::Polygon(polygon) => polygon_contains(polygon, coord.lat(), coord.lon()),
+            AlertArea::Circle {
+                lat,
+                lon,
+                radius_km,
+            } => haversine_km(*lat, *lon, coord.lat(), coord.lon()) <= *radius_km,
+        })
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires < now)
+    }
+}
+
+// Ray casting on a plain lat/lon plane. Alert polygons are small enough that
+// projection distortion doesn't matter.
+fn ring_contains(ring: &[(f64, f64)], lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (lat_i, lon_i) = ring[i];
+        let (lat_j, lon_j) = ring[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn polygon_contains(polygon: &AlertPolygon, lat: f64, lon: f64) -> bool {
+    match polygon.rings.split_first() {
+        Some((outer, holes)) => {
+            ring_contains(outer, lat, lon) && !holes.iter().any(|h| ring_contains(h, lat, lon))
+        }
+        None => false,
+    }
+}
+
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().asin()
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// --- met.no MetAlerts (GeoJSON) ---
+
+#[derive(Deserialize)]
+struct MetAlertsResponse {
+    features: Vec<MetAlertFeature>,
+}
+
+#[derive(Deserialize)]
+struct MetAlertFeature {
+    #[serde(default, deserialize_with = "lenient_geometry")]
+    geometry: Option<MetAlertGeometry>,
+    properties: MetAlertProperties,
+    when: Option<MetAlertWhen>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+enum MetAlertGeometry {
+    Polygon(Vec<Vec<[f64; 2]>>),
+    MultiPolygon(Vec<Vec<Vec<[f64; 2]>>>),
+}
+
+// Other geometry types, or ones that don't parse, leave that alert without an
+// area instead of failing the whole feed
+fn lenient_geometry<'de, D>(deserializer: D) -> Result<Option<MetAlertGeometry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|value| match serde_json::from_value(value) {
+        Ok(geometry) => Some(geometry),
+        Err(e) => {
+            tracing::warn!("Skipping alert geometry: {}", e);
+            None
+        }
+    }))
+}
+
+#[derive(Deserialize)]
+struct MetAlertProperties {
+    id: String,
+    event: Option<String>,
+    #[serde(rename = "eventAwarenessName")]
+    event_awareness_name: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    instruction: Option<String>,
+    area: Option<String>,
+    severity: Option<String>,
+    certainty: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MetAlertWhen {
+    interval: Vec<String>,
+}
+
+fn geojson_polygon(rings: Vec<Vec<[f64; 2]>>) -> AlertPolygon {
+    // GeoJSON positions are [lon, lat]
+    AlertPolygon {
+        rings: rings
+            .into_iter()
+            .map(|ring| ring.into_iter().map(|[lon, lat]| (lat, lon)).collect())
+            .collect(),
+    }
+}
+
+async fn fetch_metalerts() -> Result<Vec<WeatherAlert>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(METALERTS_URL)
+        .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+        .send_timed()
+        .await?
+        .error_for_status()?
+        .json::<MetAlertsResponse>()
+        .await?;
+
+    Ok(metalerts_from(response))
+}
+
+fn metalerts_from(response: MetAlertsResponse) -> Vec<WeatherAlert> {
+    response
+        .features
+        .into_iter()
+        .map(|feature| {
+            let areas = match feature.geometry {
+                Some(MetAlertGeometry::Polygon(rings)) => {
+                    vec![AlertArea::Polygon(geojson_polygon(rings))]
+                }
+                Some(MetAlertGeometry::MultiPolygon(polygons)) => polygons
+                    .into_iter()
+                    .map(|rings| AlertArea::Polygon(geojson_polygon(rings)))
+                    .collect(),
+                None => Vec::new(),
+            };
+            let interval = feature.when.map(|w| w.interval).unwrap_or_default();
+            let props = feature.properties;
+
+            WeatherAlert {
+                id: props.id,
+                source: "met.no".to_string(),
+                event: props
+                    .event_awareness_name
+                    .or(props.event)
+                    .unwrap_or_else(|| "Unknown".to_string()),
+                headline: props.title,
+                description: props.description,
+                instruction: props.instruction,
+                area_description: props.area,
+                severity: Severity::parse(props.severity.as_deref().unwrap_or_default()),
+                certainty: Certainty::parse(props.certainty.as_deref().unwrap_or_default()),
+                onset: interval.first().and_then(|t| parse_time(t)),
+                expires: interval.get(1).and_then(|t| parse_time(t)),
+                areas,
+            }
+        })
+        .collect()
+}
+
+// --- Generic CAP 1.2 feeds ---
+
+fn child_text<'a>(node: roxmltree::Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name((CAP_NAMESPACE, name)))
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+// CAP polygons are whitespace separated "lat,lon" pairs
+fn parse_cap_polygon(text: &str) -> Option<AlertPolygon> {
+    let ring: Vec<(f64, f64)> = text
+        .split_whitespace()
+        .filter_map(|pair| {
+            let (lat, lon) = pair.split_once(',')?;
+            Some((lat.parse().ok()?, lon.parse().ok()?))
+        })
+        .collect();
+    if ring.len() < 3 {
+        return None;
+    }
+    Some(AlertPolygon { rings: vec![ring] })
+}
+
+// CAP circles are "lat,lon radius" with the radius in kilometers
+fn parse_cap_circle(text: &str) -> Option<AlertArea> {
+    let (center, radius) = text.trim().split_once(char::is_whitespace)?;
+    let (lat, lon) = center.split_once(',')?;
+    Some(AlertArea::Circle {
+        lat: lat.parse().ok()?,
+        lon: lon.parse().ok()?,
+        radius_km: radius.trim().parse().ok()?,
+    })
+}
+
+fn parse_cap_alert(
+    xml: &str,
+    source: &str,
+) -> Result<Vec<WeatherAlert>, Box<dyn std::error::Error + Send + Sync>> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let root = doc.root_element();
+    if !root.has_tag_name((CAP_NAMESPACE, "alert")) {
+        return Err("Document is not a CAP 1.2 alert".into());
+    }
+
+    let identifier = child_text(root, "identifier").unwrap_or("unknown");
+    // Cancellations and updates reference earlier alerts; only show actual alerts
+    if child_text(root, "status") != Some("Actual") || child_text(root, "msgType") == Some("Cancel")
+    {
+        return Ok(Vec::new());
+    }
+
+    let infos: Vec<_> = root
+        .children()
+        .filter(|n| n.has_tag_name((CAP_NAMESPACE, "info")))
+        .collect();
+    // A CAP alert may repeat its info block per language; prefer English
+    let info = infos
+        .iter()
+        .find(|info| child_text(**info, "language").is_some_and(|l| l.starts_with("en")))
+        .or(infos.first());
+
+    let Some(info) = info else {
+        return Ok(Vec::new());
+    };
+
+    let mut areas = Vec::new();
+    let mut area_names = Vec::new();
+    for area in info
+        .children()
+        .filter(|n| n.has_tag_name((CAP_NAMESPACE, "area")))
+    {
+        if let Some(name) = child_text(area, "areaDesc") {
+            area_names.push(name.to_string());
+        }
+        for shape in area.children() {
+            let parsed = if shape.has_tag_name((CAP_NAMESPACE, "polygon")) {
+                shape
+                    .text()
+                    .and_then(parse_cap_polygon)
+                    .map(AlertArea::Polygon)
+            } else if shape.has_tag_name((CAP_NAMESPACE, "circle")) {
+                shape.text().and_then(parse_cap_circle)
+            } else {
+                None
+            };
+            if let Some(parsed) = parsed {
+                areas.push(parsed);
+            }
+        }
+    }
+
+    Ok(vec![WeatherAlert {
+        id: identifier.to_string(),
+        source: source.to_string(),
+        event: child_text(*info, "event").unwrap_or("Unknown").to_string(),
+        headline: child_text(*info, "headline").map(str::to_string),
+        description: child_text(*info, "description").map(str::to_string),
+        instruction: child_text(*info, "instruction").map(str::to_string),
+        area_description: (!area_names.is_empty()).then(|| area_names.join(", ")),
+        severity: Severity::parse(child_text(*info, "severity").unwrap_or_default()),
+        certainty: Certainty::parse(child_text(*info, "certainty").unwrap_or_default()),
+        onset: child_text(*info, "onset")
+            .or(child_text(*info, "effective"))
+            .and_then(parse_time),
+        expires: child_text(*info, "expires").and_then(parse_time),
+        areas,
+    }])
+}
+
+// Feeds are either a single CAP document or an Atom/RSS index linking to them
+fn feed_entry_links(xml: &str) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let links = doc
+        .descendants()
+        .filter_map(|n| match n.tag_name().name() {
+            "link" if n.parent().is_some_and(|p| p.has_tag_name("item")) => {
+                n.text().map(|t| t.trim().to_string())
+            }
+            "link" if n.parent().is_some_and(|p| p.tag_name().name() == "entry") => {
+                n.attribute("href").map(str::to_string)
+            }
+            _ => None,
+        })
+        .take(MAX_FEED_ENTRIES)
+        .collect();
+    Ok(links)
+}
+
+async fn fetch_cap_feed(
+    url: &str,
+) -> Result<Vec<WeatherAlert>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = reqwest::Client::new();
+    let source = reqwest::Url::parse(url)?
+        .host_str()
+        .unwrap_or(url)
+        .to_string();
+
+    let body = client
+        .get(url)
+        .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+        .send_timed()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    if body.contains(CAP_NAMESPACE) && !body.contains("<feed") && !body.contains("<rss") {
+        return parse_cap_alert(&body, &source);
+    }
+
+    let mut alerts = Vec::new();
+    for link in feed_entry_links(&body)? {
+        let entry = match client
+            .get(&link)
+            .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+            .send_timed()
+            .await
+        {
+            Ok(resp) => match resp.error_for_status() {
+                Ok(resp) => resp.text().await.map_err(Into::into),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e),
+        };
+        // One bad entry shouldn't cost the rest of the feed
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("Failed to fetch CAP alert {}: {}", link, e);
+                continue;
+            }
+        };
+        match parse_cap_alert(&entry, &source) {
+            Ok(parsed) => alerts.extend(parsed),
+            Err(e) => tracing::warn!("Failed to parse CAP alert {}: {}", link, e),
+        }
+    }
+    Ok(alerts)
+}
+
+fn cap_feed_urls() -> Vec<String> {
+    std::env::var("CAP_FEED_URLS")
+        .map(|urls| {
+            urls.split(',')
+                .map(str::trim)
+                .filter(|u| !u.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Problems with `CAP_FEED_URLS`, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    cap_feed_urls()
+        .into_iter()
+        .filter(|url| reqwest::Url::parse(url).is_err())
+        .map(|url| format!("Invalid CAP feed URL: {}", url))
+        .collect()
+}
+
+async fn fetch_all_alerts() -> Result<Vec<WeatherAlert>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut alerts = Vec::new();
+    let mut last_error = None;
+    let mut succeeded = 0;
+
+    match fetch_metalerts().await {
+        Ok(fetched) => {
+            alerts.extend(fetched);
+            succeeded += 1;
+        }
+        Err(e) => {
+            tracing::warn!("Failed to fetch MetAlerts: {}", e);
+            last_error = Some(e);
+        }
+    }
+
+    for url in cap_feed_urls() {
+        match fetch_cap_feed(&url).await {
+            Ok(fetched) => {
+                alerts.extend(fetched);
+                succeeded += 1;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to fetch CAP feed {}: {}", url, e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match (succeeded, last_error) {
+        (0, Some(e)) => Err(e),
+        _ => Ok(alerts),
+    }
+}
+
+/// When the alert feeds were last fetched, if they have been.
+pub async fn cached_at() -> Option<DateTime<Utc>> {
+    ALERT_CACHE.read().await.time
+}
+
+/// Active alerts whose area covers the given coordinates, most severe first.
+#[tracing::instrument(skip_all, fields(coord_key = %crate::weather::coord_key(coord)))]
+pub async fn alerts_for(
+    coord: &Coordinates,
+) -> Result<Vec<WeatherAlert>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = Utc::now();
+    // While the feeds are down, callers get what's cached (or nothing) instead
+    // of each waiting out its own upstream timeout
+    let fresh = |cache: &AlertCache| {
+        let within = |time: Option<DateTime<Utc>>, seconds| {
+            time.is_some_and(|t| now.signed_duration_since(t).num_seconds() < seconds)
+        };
+        within(cache.time, ALERT_CACHE_SECONDS) || within(cache.failed_at, FAILURE_BACKOFF_SECONDS)
+    };
+
+    let matching = {
+        let cache_read = ALERT_CACHE.read().await;
+        fresh(&cache_read).then(|| matching_alerts(&cache_read.alerts, coord, now))
+    };
+    let matching = match matching {
+        Some(matching) => matching,
+        None => {
+            // Fetching can take a while with many CAP entries, so it happens
+            // outside the cache lock, one refresh at a time
+            let _refreshing = REFRESH_LOCK.lock().await;
+            let cached = {
+                let cache_read = ALERT_CACHE.read().await;
+                fresh(&cache_read).then(|| matching_alerts(&cache_read.alerts, coord, now))
+            };
+            match cached {
+                Some(matching) => matching,
+                None => match fetch_all_alerts().await {
+                    Ok(alerts) => {
+                        let matching = matching_alerts(&alerts, coord, now);
+                        let mut cache_write = ALERT_CACHE.write().await;
+                        cache_write.alerts = alerts;
+                        cache_write.time = Some(Utc::now());
+                        cache_write.failed_at = None;
+                        matching
+                    }
+                    Err(e) => {
+                        let mut cache_write = ALERT_CACHE.write().await;
+                        cache_write.failed_at = Some(Utc::now());
+                        if cache_write.time.is_none() {
+                            return Err(e);
+                        }
+                        tracing::warn!("Serving stale alerts after a failed refresh: {}", e);
+                        matching_alerts(&cache_write.alerts, coord, now)
+                    }
+                },
+            }
+        }
+    };
+
+    events::publish(coord, EventKind::Alerts, &matching);
+    Ok(matching)
+}
+
+fn matching_alerts(
+    alerts: &[WeatherAlert],
+    coord: &Coordinates,
+    now: DateTime<Utc>,
+) -> Vec<WeatherAlert> {
+    let mut matching: Vec<WeatherAlert> = alerts
+        .iter()
+        .filter(|alert| !alert.is_expired(now) && alert.covers(coord))
+        .cloned()
+        .collect();
+    matching.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.onset.cmp(&b.onset)));
+    matching
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    // (lat, lon) corners, clockwise
+    fn square(lat: f64, lon: f64, size: f64) -> Vec<(f64, f64)> {
+        vec![
+            (lat, lon),
+            (lat + size, lon),
+            (lat + size, lon + size),
+            (lat, lon + size),
+        ]
+    }
+
+    fn alert(id: &str, severity: Severity, expires: Option<DateTime<Utc>>) -> WeatherAlert {
+        WeatherAlert {
+            id: id.to_string(),
+            source: "test".to_string(),
+            event: "Wind".to_string(),
+            headline: None,
+            description: None,
+            instruction: None,
+            area_description: None,
+            severity,
+            certainty: Certainty::Likely,
+            onset: None,
+            expires,
+            areas: vec![AlertArea::Polygon(AlertPolygon {
+                rings: vec![square(59.0, 10.0, 1.0)],
+            })],
+        }
+    }
+
+    #[test]
+    fn ring_contains_inside_and_outside() {
+        let ring = square(59.0, 10.0, 1.0);
+        assert!(ring_contains(&ring, 59.5, 10.5));
+        assert!(!ring_contains(&ring, 60.5, 10.5));
+        assert!(!ring_contains(&ring, 59.5, 9.5));
+    }
+
+    #[test]
+    fn ring_contains_shared_edge_belongs_to_one_side() {
+        let west = square(59.0, 10.0, 1.0);
+        let east = square(59.0, 11.0, 1.0);
+        let on_edge = [
+            ring_contains(&west, 59.5, 11.0),
+            ring_contains(&east, 59.5, 11.0),
+        ];
+        assert_eq!(on_edge.iter().filter(|&&inside| inside).count(), 1);
+    }
+
+    #[test]
+    fn polygon_contains_skips_holes() {
+        let polygon = AlertPolygon {
+            rings: vec![square(59.0, 10.0, 1.0), square(59.4, 10.4, 0.2)],
+        };
+        assert!(polygon_contains(&polygon, 59.1, 10.1));
+        assert!(!polygon_contains(&polygon, 59.5, 10.5));
+        assert!(!polygon_contains(&polygon, 58.5, 10.5));
+    }
+
+    #[test]
+    fn parses_cap_alert() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <alert xmlns="urn:oasis:names:tc:emergency:cap:1.2">
+              <identifier>2.49.0.1.578.0.20261018.1</identifier>
+              <status>Actual</status>
+              <msgType>Alert</msgType>
+              <info>
+                <language>no</language>
+                <event>Kuling</event>
+              </info>
+              <info>
+                <language>en-GB</language>
+                <event>Gale</event>
+                <severity>Severe</severity>
+                <certainty>Likely</certainty>
+                <onset>2026-10-18T06:00:00+00:00</onset>
+                <expires>2026-10-19T06:00:00+00:00</expires>
+                <headline>Gale warning</headline>
+                <area>
+                  <areaDesc>Oslofjord</areaDesc>
+                  <polygon>59.0,10.0 60.0,10.0 60.0,11.0 59.0,11.0 59.0,10.0</polygon>
+                  <circle>62.0,6.0 10</circle>
+                </area>
+              </info>
+            </alert>"#;
+        let alerts = parse_cap_alert(xml, "example.org").unwrap();
+        assert_eq!(alerts.len(), 1);
+        let alert = &alerts[0];
+        assert_eq!(alert.id, "2.49.0.1.578.0.20261018.1");
+        assert_eq!(alert.event, "Gale");
+        assert_eq!(alert.severity, Severity::Severe);
+        assert_eq!(alert.certainty, Certainty::Likely);
+        assert_eq!(alert.area_description.as_deref(), Some("Oslofjord"));
+        assert_eq!(
+            alert.expires,
+            Some(Utc.with_ymd_and_hms(2026, 10, 19, 6, 0, 0).unwrap())
+        );
+        assert!(alert.covers(&Coordinates::new(59.5, 10.5)));
+        assert!(alert.covers(&Coordinates::new(62.05, 6.0)));
+        assert!(!alert.covers(&Coordinates::new(63.0, 10.5)));
+    }
+
+    #[test]
+    fn skips_cancelled_cap_alerts() {
+        let xml = r#"<alert xmlns="urn:oasis:names:tc:emergency:cap:1.2">
+              <identifier>1</identifier>
+              <status>Actual</status>
+              <msgType>Cancel</msgType>
+              <info><event>Gale</event></info>
+            </alert>"#;
+        assert!(parse_cap_alert(xml, "example.org").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_metalerts() {
+        let json = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[10.0, 59.0], [11.0, 59.0], [11.0, 60.0], [10.0, 60.0], [10.0, 59.0]]]
+                    },
+                    "properties": {
+                        "id": "polygon",
+                        "event": "wind",
+                        "eventAwarenessName": "Wind",
+                        "severity": "Moderate",
+                        "certainty": "Likely"
+                    },
+                    "when": { "interval": ["2026-10-18T06:00:00+00:00", "2026-10-19T06:00:00+00:00"] }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "MultiPolygon",
+                        "coordinates": [[[[5.0, 60.0], [6.0, 60.0], [6.0, 61.0], [5.0, 60.0]]]]
+                    },
+                    "properties": { "id": "multi", "event": "rain" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [10.5, 59.5] },
+                    "properties": { "id": "point" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": null,
+                    "properties": { "id": "none" }
+                }
+            ]
+        }"#;
+        let alerts = metalerts_from(serde_json::from_str(json).unwrap());
+        let ids: Vec<&str> = alerts.iter().map(|alert| alert.id.as_str()).collect();
+        assert_eq!(ids, ["polygon", "multi", "point", "none"]);
+
+        let polygon = &alerts[0];
+        assert_eq!(polygon.event, "Wind");
+        assert_eq!(polygon.severity, Severity::Moderate);
+        assert_eq!(
+            polygon.onset,
+            Some(Utc.with_ymd_and_hms(2026, 10, 18, 6, 0, 0).unwrap())
+        );
+        // GeoJSON positions are [lon, lat]
+        assert!(polygon.covers(&Coordinates::new(59.5, 10.5)));
+        assert!(!polygon.covers(&Coordinates::new(10.5, 59.5)));
+
+        assert_eq!(alerts[1].event, "rain");
+        assert_eq!(alerts[1].severity, Severity::Unknown);
+        assert!(alerts[1].covers(&Coordinates::new(60.2, 5.8)));
+        assert!(alerts[2].areas.is_empty());
+        assert!(alerts[3].areas.is_empty());
+    }
+
+    #[test]
+    fn matching_alerts_orders_by_severity_and_drops_expired() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        let alerts = vec![
+            alert("minor", Severity::Minor, None),
+            alert(
+                "expired",
+                Severity::Extreme,
+                Some(now - chrono::Duration::hours(1)),
+            ),
+            alert(
+                "severe",
+                Severity::Severe,
+                Some(now + chrono::Duration::hours(1)),
+            ),
+            alert("moderate", Severity::Moderate, None),
+        ];
+        let matching = matching_alerts(&alerts, &Coordinates::new(59.5, 10.5), now);
+        let ids: Vec<&str> = matching.iter().map(|alert| alert.id.as_str()).collect();
+        assert_eq!(ids, ["severe", "moderate", "minor"]);
+
+        assert!(matching_alerts(&alerts, &Coordinates::new(40.0, 10.5), now).is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::location::Coordinates;
+
+// Formulas are the low precision ones from the Astronomical Almanac (as used by
+// NOAA and suncalc). They're good to about a minute for the sun and a few
+// minutes for the moon, which is plenty for a dashboard.
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+const OBLIQUITY: f64 = 23.4397;
+
+const SUNRISE_ALTITUDE: f64 = -0.833;
+const CIVIL_TWILIGHT_ALTITUDE: f64 = -6.0;
+const NAUTICAL_TWILIGHT_ALTITUDE: f64 = -12.0;
+const ASTRONOMICAL_TWILIGHT_ALTITUDE: f64 = -18.0;
+// Moon's apparent radius and horizontal parallax roughly cancel out refraction
+const MOONRISE_ALTITUDE: f64 = 0.133;
+const MOON_SAMPLE_MINUTES: i64 = 10;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PolarCondition {
+    PolarDay,
+    PolarNight,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct TwilightWindow {
+    /// Start of morning twilight
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dawn: Option<DateTime<FixedOffset>>,
+    /// End of evening twilight
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dusk: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct SunReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunrise: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<DateTime<FixedOffset>>,
+    pub solar_noon: DateTime<FixedOffset>,
+    pub civil_twilight: TwilightWindow,
+    pub nautical_twilight: TwilightWindow,
+    pub astronomical_twilight: TwilightWindow,
+    pub day_length_seconds: i64,
+    pub day_length_change_seconds: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polar: Option<PolarCondition>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MoonPhase {
+    NewMoon,
+    WaxingCrescent,
+    FirstQuarter,
+    WaxingGibbous,
+    FullMoon,
+    WaningGibbous,
+    LastQuarter,
+    WaningCrescent,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct MoonReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moonrise: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moonset: Option<DateTime<FixedOffset>>,
+    pub phase: MoonPhase,
+    /// Position in the lunation: 0 = new, 0.25 = first quarter, 0.5 = full, 0.75 = last quarter
+    pub phase_fraction: f64,
+    /// Illuminated fraction of the disc, 0 to 1
+    pub illumination: f64,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct AstronomyReport {
+    pub date: NaiveDate,
+    pub timezone: String,
+    pub sun: SunReport,
+    pub moon: MoonReport,
+}
+
+fn sin_deg(deg: f64) -> f64 {
+    deg.to_radians().sin()
+}
+
+fn cos_deg(deg: f64) -> f64 {
+    deg.to_radians().cos()
+}
+
+fn julian_to_utc(jd: f64) -> DateTime<Utc> {
+    let seconds = ((jd - UNIX_EPOCH_JD) * 86_400.0).round() as i64;
+    DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+}
+
+fn utc_to_julian(time: DateTime<Utc>) -> f64 {
+    time.timestamp_millis() as f64 / 86_400_000.0 + UNIX_EPOCH_JD
+}
+
+fn local(jd: f64, tz: &Tz) -> DateTime<FixedOffset> {
+    julian_to_utc(jd).with_timezone(tz).fixed_offset()
+}
+
+/// Solar transit and declination for the solar day nearest to `date` at `lon`.
+struct SolarDay {
+    transit: f64,
+    declination: f64,
+}
+
+fn solar_day(date: NaiveDate, lon: f64) -> SolarDay {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap_or_default();
+    let days = (date - epoch).num_days() as f64;
+    let mean_solar_time = days - lon / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let center =
+        1.9148 * sin_deg(anomaly) + 0.02 * sin_deg(2.0 * anomaly) + 0.0003 * sin_deg(3.0 * anomaly);
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+
+    let transit = J2000 + mean_solar_time + 0.0053 * sin_deg(anomaly)
+        - 0.0069 * sin_deg(2.0 * ecliptic_longitude);
+    let declination = (sin_deg(ecliptic_longitude) * sin_deg(OBLIQUITY))
+        .asin()
+        .to_degrees();
+
+    SolarDay {
+        transit,
+        declination,
+    }
+}
+
+enum Crossing {
+    Times(f64, f64),
+    AlwaysAbove,
+    AlwaysBelow,
+}
+
+fn sun_crossing(day: &SolarDay, lat: f64, altitude: f64) -> Crossing {
+    let cos_hour_angle = (sin_deg(altitude) - sin_deg(lat) * sin_deg(day.declination))
+        / (cos_deg(lat) * cos_deg(day.declination));
+    if cos_hour_angle < -1.0 {
+        Crossing::AlwaysAbove
+    } else if cos_hour_angle > 1.0 {
+        Crossing::AlwaysBelow
+    } else {
+        let half_arc = cos_hour_angle.acos().to_degrees() / 360.0;
+        Crossing::Times(day.transit - half_arc, day.transit + half_arc)
+    }
+}
+
+fn twilight(day: &SolarDay, lat: f64, altitude: f64, tz: &Tz) -> TwilightWindow {
+    match sun_crossing(day, lat, altitude) {
+        Crossing::Times(dawn, dusk) => TwilightWindow {
+            dawn: Some(local(dawn, tz)),
+            dusk: Some(local(dusk, tz)),
+        },
+        _ => TwilightWindow {
+            dawn: None,
+            dusk: None,
+        },
+    }
+}
+
+fn day_length_seconds(day: &SolarDay, lat: f64) -> i64 {
+    match sun_crossing(day, lat, SUNRISE_ALTITUDE) {
+        Crossing::Times(rise, set) => ((set - rise) * 86_400.0).round() as i64,
+        Crossing::AlwaysAbove => 86_400,
+        Crossing::AlwaysBelow => 0,
+    }
+}
+
+pub fn sun_report(coord: &Coordinates, date: NaiveDate, tz: &Tz) -> SunReport {
+    let lat = coord.lat();
+    let day = solar_day(date, coord.lon());
+    let yesterday = solar_day(date - Duration::days(1), coord.lon());
+
+    let (sunrise, sunset, polar) = match sun_crossing(&day, lat, SUNRISE_ALTITUDE) {
+        Crossing::Times(rise, set) => (Some(local(rise, tz)), Some(local(set, tz)), None),
+        Crossing::AlwaysAbove => (None, None, Some(PolarCondition::PolarDay)),
+        Crossing::AlwaysBelow => (None, None, Some(PolarCondition::PolarNight)),
+    };
+
+    let day_length = day_length_seconds(&day, lat);
+
+    SunReport {
+        sunrise,
+        sunset,
+        solar_noon: local(day.transit, tz),
+        civil_twilight: twilight(&day, lat, CIVIL_TWILIGHT_ALTITUDE, tz),
+        nautical_twilight: twilight(&day, lat, NAUTICAL_TWILIGHT_ALTITUDE, tz),
+        astronomical_twilight: twilight(&day, lat, ASTRONOMICAL_TWILIGHT_ALTITUDE, tz),
+        day_length_seconds: day_length,
+        day_length_change_seconds: day_length - day_length_seconds(&yesterday, lat),
+        polar,
+    }
+}
+
+/// Equatorial coordinates in degrees, distance in km.
+struct Equatorial {
+    right_ascension: f64,
+    declination: f64,
+    distance: f64,
+}
+
+fn ecliptic_to_equatorial(lon: f64, lat: f64, distance: f64) -> Equatorial {
+    let right_ascension = (sin_deg(lon) * cos_deg(OBLIQUITY)
+        - lat.to_radians().tan() * sin_deg(OBLIQUITY))
+    .atan2(cos_deg(lon))
+    .to_degrees();
+    let declination = (sin_deg(lat) * cos_deg(OBLIQUITY)
+        + cos_deg(lat) * sin_deg(OBLIQUITY) * sin_deg(lon))
+    .asin()
+    .to_degrees();
+    Equatorial {
+        right_ascension,
+        declination,
+        distance,
+    }
+}
+
+fn sun_position(jd: f64) -> Equatorial {
+    let d = jd - J2000;
+    let anomaly = 357.5291 + 0.98560028 * d;
+    let center =
+        1.9148 * sin_deg(anomaly) + 0.02 * sin_deg(2.0 * anomaly) + 0.0003 * sin_deg(3.0 * anomaly);
+    let ecliptic_longitude = anomaly + center + 180.0 + 102.9372;
+    ecliptic_to_equatorial(ecliptic_longitude, 0.0, 149_598_000.0)
+}
+
+fn moon_position(jd: f64) -> Equatorial {
+    let d = jd - J2000;
+    let mean_longitude = 218.316 + 13.176396 * d;
+    let anomaly = 134.963 + 13.064993 * d;
+    let argument_of_latitude = 93.272 + 13.229350 * d;
+
+    let lon = mean_longitude + 6.289 * sin_deg(anomaly);
+    let lat = 5.128 * sin_deg(argument_of_latitude);
+    let distance = 385_001.0 - 20_905.0 * cos_deg(anomaly);
+    ecliptic_to_equatorial(lon, lat, distance)
+}
+
+fn moon_altitude(jd: f64, lat: f64, lon: f64) -> f64 {
+    let moon = moon_position(jd);
+    let sidereal_time = 280.16 + 360.9856235 * (jd - J2000) + lon;
+    let hour_angle = sidereal_time - moon.right_ascension;
+    (sin_deg(lat) * sin_deg(moon.declination)
+        + cos_deg(lat) * cos_deg(moon.declination) * cos_deg(hour_angle))
+    .asin()
+    .to_degrees()
+}
+
+fn moon_rise_set(
+    coord: &Coordinates,
+    date: NaiveDate,
+    tz: &Tz,
+) -> (Option<DateTime<FixedOffset>>, Option<DateTime<FixedOffset>>) {
+    let midnight = |date: NaiveDate| {
+        tz.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .earliest()
+            .map(|t| utc_to_julian(t.with_timezone(&Utc)))
+    };
+    let (Some(start), Some(end)) = (midnight(date), midnight(date + Duration::days(1))) else {
+        return (None, None);
+    };
+    let step = MOON_SAMPLE_MINUTES as f64 / 1440.0;
+    // Up to the next local midnight, as the day has 23 or 25 hours when the
+    // clocks change
+    let samples = ((end - start) / step).ceil() as i64;
+
+    let height = |jd: f64| moon_altitude(jd, coord.lat(), coord.lon()) - MOONRISE_ALTITUDE;
+
+    let mut rise = None;
+    let mut set = None;
+    let mut prev_jd = start;
+    let mut prev_height = height(prev_jd);
+    for i in 1..=samples {
+        let jd = (start + step * i as f64).min(end);
+        let h = height(jd);
+        if (prev_height < 0.0) != (h < 0.0) {
+            // Linear interpolation is good enough at 10 minute steps
+            let crossing = prev_jd + (jd - prev_jd) * (prev_height / (prev_height - h));
+            if h > 0.0 {
+                rise.get_or_insert(local(crossing, tz));
+            } else {
+                set.get_or_insert(local(crossing, tz));
+            }
+        }
+        prev_jd = jd;
+        prev_height = h;
+    }
+    (rise, set)
+}
+
+fn moon_report(coord: &Coordinates, date: NaiveDate, tz: &Tz) -> MoonReport {
+    let (moonrise, moonset) = moon_rise_set(coord, date, tz);
+
+    // Illumination at local noon
+    let noon = tz
+        .from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap_or_default())
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+    let jd = utc_to_julian(noon);
+    let sun = sun_position(jd);
+    let moon = moon_position(jd);
+
+    let elongation = (sin_deg(sun.declination) * sin_deg(moon.declination)
+        + cos_deg(sun.declination)
+            * cos_deg(moon.declination)
+            * cos_deg(sun.right_ascension - moon.right_ascension))
+    .clamp(-1.0, 1.0)
+    .acos();
+    let incidence =
+        (sun.distance * elongation.sin()).atan2(moon.distance - sun.distance * elongation.cos());
+    let direction =
+        (cos_deg(sun.declination) * sin_deg(sun.right_ascension - moon.right_ascension)).atan2(
+            sin_deg(sun.declination) * cos_deg(moon.declination)
+                - cos_deg(sun.declination)
+                    * sin_deg(moon.declination)
+                    * cos_deg(sun.right_ascension - moon.right_ascension),
+        );
+
+    let illumination = (1.0 + incidence.cos()) / 2.0;
+    let phase_fraction = 0.5 + 0.5 * incidence * direction.signum() / std::f64::consts::PI;
+
+    MoonReport {
+        moonrise,
+        moonset,
+        phase: moon_phase(phase_fraction),
+        phase_fraction,
+        illumination,
+    }
+}
+
+fn moon_phase(fraction: f64) -> MoonPhase {
+    // The named quarter phases get a window of about a day either side
+    const QUARTER_WINDOW: f64 = 0.034;
+    match fraction {
+        f if !(QUARTER_WINDOW..1.0 - QUARTER_WINDOW).contains(&f) => MoonPhase::NewMoon,
+        f if (f - 0.25).abs() < QUARTER_WINDOW => MoonPhase::FirstQuarter,
+        f if (f - 0.5).abs() < QUARTER_WINDOW => MoonPhase::FullMoon,
+        f if (f - 0.75).abs() < QUARTER_WINDOW => MoonPhase::LastQuarter,
+        f if f < 0.25 => MoonPhase::WaxingCrescent,
+        f if f < 0.5 => MoonPhase::WaxingGibbous,
+        f if f < 0.75 => MoonPhase::WaningGibbous,
+        _ => MoonPhase::WaningCrescent,
+    }
+}
+
+pub fn astronomy_for(coord: &Coordinates, date: NaiveDate, tz: Tz) -> AstronomyReport {
+    AstronomyReport {
+        date,
+        timezone: tz.name().to_string(),
+        sun: sun_report(coord, date, &tz),
+        moon: moon_report(coord, date, &tz),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Datelike;
+
+    use super::*;
+
+    const OSLO: (f64, f64) = (59.9139, 10.7522);
+    const TROMSO: (f64, f64) = (69.6492, 18.9553);
+
+    fn oslo_tz() -> Tz {
+        "Europe/Oslo".parse().unwrap()
+    }
+
+    fn coordinates((lat, lon): (f64, f64)) -> Coordinates {
+        Coordinates::new(lat, lon)
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    // Published times are to the minute, and these formulas are good to about one
+    fn assert_near(actual: Option<DateTime<FixedOffset>>, expected: &str) {
+        let expected = DateTime::parse_from_rfc3339(expected).unwrap();
+        let actual = actual.expect("a time");
+        let off = (actual - expected).num_seconds().abs();
+        assert!(off <= 120, "{} is {}s from {}", actual, off, expected);
+    }
+
+    #[test]
+    fn oslo_summer_solstice() {
+        let sun = sun_report(&coordinates(OSLO), date("2026-06-21"), &oslo_tz());
+        assert_near(sun.sunrise, "2026-06-21T03:54:00+02:00");
+        assert_near(sun.sunset, "2026-06-21T22:44:00+02:00");
+        assert!(sun.polar.is_none());
+        // The sun dips below -6° but never -12°, so there's no nautical night
+        assert!(sun.civil_twilight.dusk.is_some());
+        assert!(sun.nautical_twilight.dawn.is_none());
+        assert!(sun.astronomical_twilight.dusk.is_none());
+    }
+
+    #[test]
+    fn oslo_winter_solstice() {
+        let sun = sun_report(&coordinates(OSLO), date("2026-12-21"), &oslo_tz());
+        assert_near(sun.sunrise, "2026-12-21T09:18:00+01:00");
+        assert_near(sun.sunset, "2026-12-21T15:12:00+01:00");
+        assert_near(sun.civil_twilight.dawn, "2026-12-21T08:21:00+01:00");
+        assert_near(sun.civil_twilight.dusk, "2026-12-21T16:09:00+01:00");
+        assert!((sun.day_length_seconds - (5 * 3600 + 54 * 60)).abs() <= 120);
+    }
+
+    #[test]
+    fn tromso_polar_night_and_midnight_sun() {
+        let winter = sun_report(&coordinates(TROMSO), date("2026-12-21"), &oslo_tz());
+        assert_eq!(winter.polar, Some(PolarCondition::PolarNight));
+        assert!(winter.sunrise.is_none() && winter.sunset.is_none());
+        assert_eq!(winter.day_length_seconds, 0);
+        // The sun stays within 6° of the horizon around noon
+        assert!(winter.civil_twilight.dawn.is_some());
+
+        let summer = sun_report(&coordinates(TROMSO), date("2026-06-21"), &oslo_tz());
+        assert_eq!(summer.polar, Some(PolarCondition::PolarDay));
+        assert_eq!(summer.day_length_seconds, 86_400);
+    }
+
+    #[test]
+    fn moon_phases_match_eclipses() {
+        let oslo = coordinates(OSLO);
+        let tz = oslo_tz();
+        // The total lunar eclipse of 3 March 2026 is at full moon
+        let full = moon_report(&oslo, date("2026-03-03"), &tz);
+        assert_eq!(full.phase, MoonPhase::FullMoon);
+        assert!(full.illumination > 0.98);
+        // and the solar eclipses of 17 February and 12 August 2026 at new moon
+        for day in ["2026-02-17", "2026-08-12"] {
+            let new = moon_report(&oslo, date(day), &tz);
+            assert_eq!(new.phase, MoonPhase::NewMoon, "{}", day);
+            assert!(new.illumination < 0.02, "{}", day);
+        }
+    }
+
+    // Minute by minute over the whole local day, however long it is
+    fn brute_force_moon_rise_set(coord: &Coordinates, date: NaiveDate, tz: &Tz) -> (usize, usize) {
+        let midnight = |date: NaiveDate| {
+            tz.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .earliest()
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let (start, end) = (midnight(date), midnight(date + Duration::days(1)));
+        let above = |t: DateTime<Utc>| {
+            moon_altitude(utc_to_julian(t), coord.lat(), coord.lon()) > MOONRISE_ALTITUDE
+        };
+        let (mut rises, mut sets) = (0, 0);
+        let mut t = start;
+        while t < end {
+            let next = (t + Duration::minutes(1)).min(end);
+            match (above(t), above(next)) {
+                (false, true) => rises += 1,
+                (true, false) => sets += 1,
+                _ => (),
+            }
+            t = next;
+        }
+        (rises, sets)
+    }
+
+    #[test]
+    fn moon_rise_set_covers_days_with_clock_changes() {
+        let oslo = coordinates(OSLO);
+        let tz = oslo_tz();
+        for year in 2000..=2050 {
+            let last_sunday = |month: u32| {
+                let last = NaiveDate::from_ymd_opt(year, month, 31).unwrap();
+                last - Duration::days(last.weekday().num_days_from_sunday() as i64)
+            };
+            for day in [last_sunday(3), last_sunday(10)] {
+                let (rise, set) = moon_rise_set(&oslo, day, &tz);
+                for time in rise.iter().chain(set.iter()) {
+                    assert_eq!(time.date_naive(), day);
+                }
+                let (rises, sets) = brute_force_moon_rise_set(&oslo, day, &tz);
+                assert_eq!(rise.is_some(), rises > 0, "moonrise on {}", day);
+                assert_eq!(set.is_some(), sets > 0, "moonset on {}", day);
+            }
+        }
+    }
+}
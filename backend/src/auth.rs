@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{
+        SaltString,
+        rand_core::{OsRng, RngCore},
+    },
+};
+use axum::{
+    Extension, Json,
+    extract::{Path, Request},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{openapi::ErrorMessage, store};
+
+const USER_NAMESPACE: &str = "user";
+const SESSION_NAMESPACE: &str = "session";
+const TOKEN_NAMESPACE: &str = "api_token";
+const SESSION_DAYS: i64 = 30;
+const SESSION_COOKIE: &str = "session";
+// EventSource and WebSocket clients can't set headers, so tokens may come in
+// the query string too
+const TOKEN_QUERY_PARAM: &str = "access_token";
+
+type AuthResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Everything the dashboard widgets read
+    Read,
+    /// Saving dashboards and places; implies read
+    Write,
+    /// The admin API; implies the others
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Whether holding this scope is enough for `other`.
+    pub fn implies(self, other: Scope) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::Write => other != Scope::Admin,
+            Scope::Read => other == Scope::Read,
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Scope> {
+        match scope.trim() {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    StaticToken,
+    ApiToken,
+    Session,
+}
+
+/// Whoever made a request, as worked out by `authenticate`.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Principal {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub method: AuthMethod,
+}
+
+impl Principal {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|&s| s.implies(scope))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct User {
+    password_hash: String,
+    scopes: Vec<Scope>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserInfo {
+    pub username: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Session {
+    username: String,
+}
+
+/// A token a user made for a device. Only a hash of the token itself is kept.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// With `AUTH_REQUIRED=true` anonymous requests to the API are refused;
+/// otherwise only the admin API needs credentials.
+pub fn auth_required() -> bool {
+    matches!(
+        std::env::var("AUTH_REQUIRED").as_deref().map(str::trim),
+        Ok("true" | "1")
+    )
+}
+
+// `ADMIN_TOKEN` plus the `API_TOKENS` list of `name:token[:scope+scope]`,
+// where the scopes default to read
+fn static_tokens() -> Vec<(String, Principal)> {
+    let mut tokens = Vec::new();
+    if let Some(token) = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) {
+        tokens.push((
+            token,
+            Principal {
+                name: "admin".to_string(),
+                scopes: vec![Scope::Admin],
+                method: AuthMethod::StaticToken,
+            },
+        ));
+    }
+    for entry in std::env::var("API_TOKENS").unwrap_or_default().split(',') {
+        let mut parts = entry.trim().splitn(3, ':');
+        let (Some(name), Some(token)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if name.is_empty() || token.is_empty() {
+            continue;
+        }
+        let scopes = match parts.next() {
+            Some(scopes) => scopes.split('+').filter_map(Scope::parse).collect(),
+            None => vec![Scope::Read],
+        };
+        tokens.push((
+            token.to_string(),
+            Principal {
+                name: name.to_string(),
+                scopes,
+                method: AuthMethod::StaticToken,
+            },
+        ));
+    }
+    tokens
+}
+
+/// Problems with the auth configuration, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    let malformed = std::env::var("API_TOKENS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| {
+            let parts: Vec<&str> = entry.splitn(3, ':').collect();
+            parts.len() < 2
+                || parts[0].is_empty()
+                || parts[1].is_empty()
+                || parts
+                    .get(2)
+                    .is_some_and(|scopes| scopes.split('+').any(|s| Scope::parse(s).is_none()))
+        })
+        .count();
+    if malformed > 0 {
+        vec![format!("API_TOKENS has {} malformed entries", malformed)]
+    } else {
+        Vec::new()
+    }
+}
+
+// Compares in time independent of where the inputs first differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn hash_password(password: &str) -> AuthResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| e.to_string())?
+        .to_string())
+}
+
+// Checked against when the user doesn't exist, so a failed login takes as long
+// either way and doesn't reveal which usernames are taken
+static DUMMY_PASSWORD_HASH: Lazy<String> =
+    Lazy::new(|| hash_password(&generate_token()).unwrap_or_default());
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+// Credentials are copied out of the request so nothing borrows it across awaits
+struct Credentials {
+    token: Option<String>,
+    session: Option<String>,
+}
+
+impl Credentials {
+    fn from_request(request: &Request) -> Self {
+        let header_token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let query_token = request.uri().query().and_then(|query| {
+            query.split('&').find_map(|pair| {
+                pair.strip_prefix(TOKEN_QUERY_PARAM)
+                    .and_then(|rest| rest.strip_prefix('='))
+                    .map(str::to_string)
+            })
+        });
+        Credentials {
+            token: header_token.or(query_token),
+            session: session_cookie(request.headers()),
+        }
+    }
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then(|| value.to_string())
+        })
+}
+
+fn session_cookie_header(value: &str, max_age: i64) -> String {
+    let secure = matches!(
+        std::env::var("SESSION_COOKIE_SECURE")
+            .as_deref()
+            .map(str::trim),
+        Ok("true" | "1")
+    );
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        SESSION_COOKIE,
+        value,
+        max_age,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+// Tokens and sessions only ever carry the scopes their user still has, so
+// deleting or demoting a user takes effect immediately
+async fn user_principal(
+    username: &str,
+    scopes: Option<&[Scope]>,
+    method: AuthMethod,
+) -> AuthResult<Option<Principal>> {
+    let Some(user) = store::get::<User>(USER_NAMESPACE, username).await? else {
+        return Ok(None);
+    };
+    let scopes = match scopes {
+        Some(scopes) => scopes
+            .iter()
+            .copied()
+            .filter(|&scope| user.scopes.iter().any(|s| s.implies(scope)))
+            .collect(),
+        None => user.scopes,
+    };
+    Ok(Some(Principal {
+        name: username.to_string(),
+        scopes,
+        method,
+    }))
+}
+
+async fn resolve(credentials: Credentials) -> AuthResult<Option<Principal>> {
+    if let Some(token) = credentials.token {
+        for (configured, principal) in static_tokens() {
+            if constant_time_eq(token.as_bytes(), configured.as_bytes()) {
+                return Ok(Some(principal));
+            }
+        }
+        let Some(api_token) = store::get::<ApiToken>(TOKEN_NAMESPACE, &hash_token(&token)).await?
+        else {
+            return Ok(None);
+        };
+        return user_principal(
+            &api_token.username,
+            Some(&api_token.scopes),
+            AuthMethod::ApiToken,
+        )
+        .await;
+    }
+    if let Some(session) = credentials.session {
+        let Some(session) = store::get::<Session>(SESSION_NAMESPACE, &hash_token(&session)).await?
+        else {
+            return Ok(None);
+        };
+        return user_principal(&session.username, None, AuthMethod::Session).await;
+    }
+    Ok(None)
+}
+
+/// Works out who is calling from a bearer token, `access_token` query
+/// parameter or session cookie, and leaves it in the request extensions.
+/// Requests are never refused here; that's up to `require_read` and
+/// `require_admin`.
+pub async fn authenticate(mut request: Request, next: Next) -> Response {
+    let credentials = Credentials::from_request(&request);
+    match resolve(credentials).await {
+        Ok(Some(principal)) => {
+            tracing::Span::current().record("principal", principal.name.as_str());
+            request.extensions_mut().insert(principal);
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to check credentials: {}", e),
+    }
+    next.run(request).await
+}
+
+// Anyone who could create an admin session or token at all
+async fn admin_configured() -> bool {
+    if static_tokens()
+        .iter()
+        .any(|(_, principal)| principal.allows(Scope::Admin))
+    {
+        return true;
+    }
+    store::load_namespace::<User>(USER_NAMESPACE)
+        .await
+        .is_ok_and(|users| users.values().any(|u| u.scopes.contains(&Scope::Admin)))
+}
+
+async fn require(scope: Scope, request: Request, next: Next) -> Response {
+    match request.extensions().get::<Principal>() {
+        Some(principal) if principal.allows(scope) => next.run(request).await,
+        Some(_) => (StatusCode::FORBIDDEN, "Insufficient scope").into_response(),
+        None if scope == Scope::Read && !auth_required() => next.run(request).await,
+        None if scope == Scope::Admin && !admin_configured().await => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Admin API is disabled; set ADMIN_TOKEN to enable it",
+        )
+            .into_response(),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Authentication required",
+        )
+            .into_response(),
+    }
+}
+
+/// Lets a request through with the read scope, or without credentials
+/// unless `AUTH_REQUIRED` is set.
+pub async fn require_read(request: Request, next: Next) -> Response {
+    require(Scope::Read, request, next).await
+}
+
+/// Lets a request through only with the write scope, even without
+/// `AUTH_REQUIRED`: saved dashboards and places aren't for passers-by to
+/// change.
+pub async fn require_write(request: Request, next: Next) -> Response {
+    require(Scope::Write, request, next).await
+}
+
+/// Lets a request through only with the admin scope. Without any way to get
+/// one the admin API is disabled altogether.
+pub async fn require_admin(request: Request, next: Next) -> Response {
+    require(Scope::Admin, request, next).await
+}
+
+/// Creates or replaces a user.
+pub async fn set_user(username: &str, password: &str, scopes: Vec<Scope>) -> AuthResult<()> {
+    let password = password.to_string();
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+    let created_at = store::get::<User>(USER_NAMESPACE, username)
+        .await?
+        .map_or_else(Utc::now, |user| user.created_at);
+    let user = User {
+        password_hash,
+        scopes,
+        created_at,
+    };
+    store::upsert(USER_NAMESPACE, username, &user, None, None).await
+}
+
+/// Deletes a user along with their API tokens; returns whether there was one.
+/// Their sessions stop working right away and expire later.
+pub async fn delete_user(username: &str) -> AuthResult<bool> {
+    let tokens: HashMap<String, ApiToken> = store::load_namespace(TOKEN_NAMESPACE).await?;
+    for (hash, token) in tokens {
+        if token.username == username {
+            store::delete(TOKEN_NAMESPACE, &hash).await?;
+        }
+    }
+    store::delete(USER_NAMESPACE, username).await
+}
+
+pub async fn users() -> AuthResult<Vec<UserInfo>> {
+    let users: HashMap<String, User> = store::load_namespace(USER_NAMESPACE).await?;
+    let mut users: Vec<UserInfo> = users
+        .into_iter()
+        .map(|(username, user)| UserInfo {
+            username,
+            scopes: user.scopes,
+            created_at: user.created_at,
+        })
+        .collect();
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+    Ok(users)
+}
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+// Tokens belong to users, so static tokens can't manage them
+fn token_owner(principal: Option<Extension<Principal>>) -> Result<Principal, (StatusCode, String)> {
+    match principal {
+        Some(Extension(principal)) if principal.method != AuthMethod::StaticToken => Ok(principal),
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Only users can manage API tokens".to_string(),
+        )),
+        None => Err((
+            StatusCode::UNAUTHORIZED,
+            "Authentication required".to_string(),
+        )),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+/// Starts a session, set as an HTTP-only cookie.
+#[utoipa::path(
+    post,
+    operation_id = "login",
+    path = "/api/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, body = Principal, headers(("set-cookie" = String))),
+        (status = 401, response = ErrorMessage),
+        (status = 429, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(username = %login.username))]
+pub async fn handle_login(
+    Json(login): Json<LoginRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let user = store::get::<User>(USER_NAMESPACE, &login.username)
+        .await
+        .map_err(internal_error)?;
+    let password = login.password;
+    let verified = tokio::task::spawn_blocking(move || match user {
+        Some(user) => verify_password(&password, &user.password_hash),
+        None => {
+            verify_password(&password, &DUMMY_PASSWORD_HASH);
+            false
+        }
+    })
+    .await
+    .map_err(internal_error)?;
+    if !verified {
+        tracing::info!("Failed login");
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Invalid username or password".to_string(),
+        ));
+    }
+
+    let session = generate_token();
+    let expires_at = Utc::now() + Duration::days(SESSION_DAYS);
+    store::upsert(
+        SESSION_NAMESPACE,
+        &hash_token(&session),
+        &Session {
+            username: login.username.clone(),
+        },
+        Some(expires_at),
+        None,
+    )
+    .await
+    .map_err(internal_error)?;
+    let principal = user_principal(&login.username, None, AuthMethod::Session)
+        .await
+        .map_err(internal_error)?;
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            session_cookie_header(&session, SESSION_DAYS * 24 * 60 * 60),
+        )],
+        Json(principal),
+    )
+        .into_response())
+}
+
+/// Ends the session in the cookie, if there is one.
+#[utoipa::path(
+    post,
+    operation_id = "logout",
+    path = "/api/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Logged out", headers(("set-cookie" = String))),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+pub async fn handle_logout(headers: HeaderMap) -> Result<Response, (StatusCode, String)> {
+    if let Some(session) = session_cookie(&headers) {
+        store::delete(SESSION_NAMESPACE, &hash_token(&session))
+            .await
+            .map_err(internal_error)?;
+    }
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(header::SET_COOKIE, session_cookie_header("", 0))],
+    )
+        .into_response())
+}
+
+/// Whoever the request is authenticated as.
+#[utoipa::path(
+    get,
+    operation_id = "getMe",
+    path = "/api/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, body = Principal),
+        (status = 401, response = ErrorMessage)
+    )
+)]
+pub async fn handle_me(
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<Principal>, (StatusCode, String)> {
+    match principal {
+        Some(Extension(principal)) => Ok(Json(principal)),
+        None => Err((StatusCode::UNAUTHORIZED, "Not authenticated".to_string())),
+    }
+}
+
+/// API tokens of the signed-in user.
+#[utoipa::path(
+    get,
+    operation_id = "listTokens",
+    path = "/api/auth/tokens",
+    tag = "auth",
+    responses(
+        (status = 200, body = Vec<ApiToken>),
+        (status = 401, response = ErrorMessage),
+        (status = 403, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+pub async fn handle_list_tokens(
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<Vec<ApiToken>>, (StatusCode, String)> {
+    let owner = token_owner(principal)?;
+    let tokens: HashMap<String, ApiToken> = store::load_namespace(TOKEN_NAMESPACE)
+        .await
+        .map_err(internal_error)?;
+    let mut tokens: Vec<ApiToken> = tokens
+        .into_values()
+        .filter(|token| token.username == owner.name)
+        .collect();
+    tokens.sort_by_key(|token| token.created_at);
+    Ok(Json(tokens))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateTokenRequest {
+    name: String,
+    /// Defaults to the scopes of the user creating it
+    scopes: Option<Vec<Scope>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedToken {
+    /// Only ever shown here; the store keeps a hash
+    token: String,
+    #[serde(flatten)]
+    info: ApiToken,
+}
+
+/// Makes an API token for a device; the token is only shown in this response.
+#[utoipa::path(
+    post,
+    operation_id = "createToken",
+    path = "/api/auth/tokens",
+    tag = "auth",
+    request_body = CreateTokenRequest,
+    responses(
+        (status = 201, body = CreatedToken),
+        (status = 401, response = ErrorMessage),
+        (status = 403, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+pub async fn handle_create_token(
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedToken>), (StatusCode, String)> {
+    let owner = token_owner(principal)?;
+    let scopes = request.scopes.unwrap_or_else(|| owner.scopes.clone());
+    if let Some(scope) = scopes.iter().find(|&&scope| !owner.allows(scope)) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("You don't have the {} scope yourself", scope.as_str()),
+        ));
+    }
+
+    let token = generate_token();
+    let hash = hash_token(&token);
+    let info = ApiToken {
+        id: hash[..12].to_string(),
+        name: request.name,
+        username: owner.name,
+        scopes,
+        created_at: Utc::now(),
+    };
+    store::upsert(TOKEN_NAMESPACE, &hash, &info, None, None)
+        .await
+        .map_err(internal_error)?;
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, info })))
+}
+
+#[utoipa::path(
+    delete,
+    operation_id = "deleteToken",
+    path = "/api/auth/tokens/{id}",
+    tag = "auth",
+    params(("id" = String, Path, description = "Token id, as listed")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, response = ErrorMessage),
+        (status = 403, response = ErrorMessage),
+        (status = 404, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+pub async fn handle_delete_token(
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let owner = token_owner(principal)?;
+    let tokens: HashMap<String, ApiToken> = store::load_namespace(TOKEN_NAMESPACE)
+        .await
+        .map_err(internal_error)?;
+    let Some((hash, _)) = tokens
+        .into_iter()
+        .find(|(_, token)| token.id == id && token.username == owner.name)
+    else {
+        return Err((StatusCode::NOT_FOUND, format!("No token {}", id)));
+    };
+    store::delete(TOKEN_NAMESPACE, &hash)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,292 @@
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    air_quality, alerts, diagnostics, location, store,
+    weather::{self, PublicWeatherResponse},
+};
+
+/// The namespaces `cache clear` empties by default: everything that's
+/// fetched again on demand, but not users, sessions or usage counters.
+const CACHE_NAMESPACES: &[&str] = &[
+    location::LOCATION_NAMESPACE,
+    weather::WEATHER_NAMESPACE,
+    weather::WEATHER_SUMMARY_NAMESPACE,
+    weather::OCEAN_NAMESPACE,
+    air_quality::AIR_QUALITY_NAMESPACE,
+];
+const DEFAULT_BIND: &str = "0.0.0.0:2001";
+
+type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Parser)]
+#[command(
+    name = "home-backend",
+    version,
+    about = "Backend for the home dashboard"
+)]
+pub struct Cli {
+    /// Environment file to load instead of `.env`
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Where the database lives [default: $CACHE_DIR or cache]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve {
+        /// Address to listen on [default: $BIND_ADDRESS or 0.0.0.0:2001]
+        #[arg(long)]
+        bind: Option<SocketAddr>,
+    },
+    /// Print the forecast for a location
+    Weather {
+        location: String,
+        /// How many hours to show
+        #[arg(long, default_value_t = 12)]
+        hours: usize,
+        /// Print the whole forecast as JSON instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the summary for a location
+    Summary {
+        location: String,
+        /// IANA timezone the summary talks in, e.g. Europe/Oslo
+        #[arg(long)]
+        timezone: Option<String>,
+    },
+    /// Look up the coordinates of a location
+    Geocode { query: String },
+    /// Inspect or manage the store
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Check the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Print database size and entries per namespace
+    Stats,
+    /// Empty the caches; a running server keeps what it has in memory
+    Clear {
+        /// Namespaces to empty, instead of all fetched data
+        #[arg(long = "namespace", value_name = "NAMESPACE")]
+        namespaces: Vec<String>,
+    },
+    /// Write entries as JSON, for backups or moving to another host
+    Export {
+        /// File to write to instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Namespaces to export, instead of all of them
+        #[arg(long = "namespace", value_name = "NAMESPACE")]
+        namespaces: Vec<String>,
+    },
+    /// Read entries written by `cache export`, replacing existing ones
+    Import {
+        /// File to read, or `-` for stdin
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Report configuration problems; exits non-zero if there are any
+    Check,
+}
+
+impl Cli {
+    /// Loads `--config`, or `.env` if there is one. Everything reads the
+    /// environment with `std::env::var`, as `dotenv::var` would load `.env`
+    /// on top of `--config`.
+    pub fn load_config(&self) -> Result<(), dotenv::Error> {
+        match &self.config {
+            Some(path) => dotenv::from_path(path),
+            None => dotenv::dotenv().map(|_| ()).or(Ok(())),
+        }
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .or_else(|| std::env::var("CACHE_DIR").ok().map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(store::DEFAULT_CACHE_DIR))
+    }
+}
+
+/// Where `serve` listens without `--bind`.
+pub fn default_bind() -> Result<SocketAddr, std::net::AddrParseError> {
+    std::env::var("BIND_ADDRESS")
+        .unwrap_or_else(|_| DEFAULT_BIND.to_string())
+        .parse()
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> CliResult {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_forecast(forecast: &PublicWeatherResponse, hours: usize) {
+    println!(
+        "{:<17} {:>7} {:>8} {:>8}  conditions",
+        "time (UTC)", "temp °C", "precip", "wind m/s"
+    );
+    let fmt = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.1}", v));
+    for entry in forecast.properties.timeseries.iter().take(hours) {
+        let instant = entry.data.instant.details.as_ref();
+        let next_hour = entry.data.next_1_hours.as_ref();
+        println!(
+            "{:<17} {:>7} {:>8} {:>8}  {}",
+            entry.time.replace('T', " ").trim_end_matches(":00Z"),
+            fmt(instant.and_then(|d| d.air_temperature)),
+            fmt(next_hour
+                .and_then(|f| f.details.as_ref())
+                .and_then(|d| d.precipitation_amount)),
+            fmt(instant.and_then(|d| d.wind_speed)),
+            next_hour.map_or("-", |f| f.summary.symbol_code.as_str()),
+        );
+    }
+}
+
+async fn check_config() -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut problems = diagnostics::config_problems();
+    if let Err(e) = default_bind() {
+        problems.push(format!("BIND_ADDRESS is not a valid address: {}", e));
+    }
+    if let Err(e) = store::check_writable() {
+        problems.push(format!(
+            "Cache directory {} is not writable: {}",
+            store::cache_dir().display(),
+            e
+        ));
+    } else if let Err(e) = store::check_database().await {
+        problems.push(format!("Database doesn't open: {}", e));
+    }
+
+    if problems.is_empty() {
+        println!("Configuration OK");
+    }
+    for problem in &problems {
+        println!("- {}", problem);
+    }
+    Ok(problems.is_empty())
+}
+
+async fn run_cache(command: CacheCommand) -> CliResult {
+    match command {
+        CacheCommand::Stats => print_json(&store::stats().await?)?,
+        CacheCommand::Clear { namespaces } => {
+            let namespaces = if namespaces.is_empty() {
+                CACHE_NAMESPACES.iter().map(|n| n.to_string()).collect()
+            } else {
+                namespaces
+            };
+            let removed = store::clear(namespaces).await?;
+            println!("Removed {} entries", removed);
+        }
+        CacheCommand::Export { output, namespaces } => {
+            let entries = store::export(namespaces).await?;
+            let json = serde_json::to_string_pretty(&entries)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, json)?;
+                    eprintln!("Exported {} entries to {}", entries.len(), path.display());
+                }
+                None => std::io::stdout().write_all(json.as_bytes())?,
+            }
+        }
+        CacheCommand::Import { file } => {
+            let json = if file.as_os_str() == "-" {
+                let mut json = String::new();
+                std::io::stdin().read_to_string(&mut json)?;
+                json
+            } else {
+                std::fs::read_to_string(&file)?
+            };
+            let imported = store::import(serde_json::from_str(&json)?).await?;
+            println!("Imported {} entries", imported);
+        }
+    }
+    Ok(())
+}
+
+/// Runs a one-off command and reports how it went.
+pub async fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Serve { .. } => unreachable!("serve is handled by main"),
+        Command::Weather {
+            location,
+            hours,
+            json,
+        } => {
+            async {
+                let coords = location::get_coordinates(&location).await?;
+                let forecast = weather::fetch_weather(&coords).await?;
+                if json {
+                    print_json(&forecast)
+                } else {
+                    print_forecast(&forecast, hours);
+                    Ok(())
+                }
+            }
+            .await
+        }
+        Command::Summary { location, timezone } => {
+            async {
+                let coords = location::get_coordinates(&location).await?;
+                let forecast = weather::fetch_weather(&coords).await?;
+                let active_alerts = alerts::alerts_for(&coords).await.unwrap_or_else(|e| {
+                    tracing::warn!("Failed to fetch weather alerts: {}", e);
+                    Vec::new()
+                });
+                let summary =
+                    weather::summarize_weather(&forecast, &active_alerts, timezone).await?;
+                println!("{}", summary);
+                Ok(())
+            }
+            .await
+        }
+        Command::Geocode { query } => {
+            async {
+                let coords = location::get_coordinates(&query).await?;
+                println!("{} {}", coords.lat(), coords.lon());
+                Ok(())
+            }
+            .await
+        }
+        Command::Cache { command } => run_cache(command).await,
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => match check_config().await {
+            Ok(true) => Ok(()),
+            Ok(false) => return ExitCode::FAILURE,
+            Err(e) => Err(e),
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
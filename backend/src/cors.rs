@@ -0,0 +1,166 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::logging;
+
+const DEFAULT_METHODS: &[Method] = &[
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+// Listed rather than `Any`, which browsers don't accept alongside credentials
+const ALLOWED_HEADERS: [HeaderName; 5] = [
+    header::AUTHORIZATION,
+    header::CONTENT_TYPE,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    HeaderName::from_static("last-event-id"),
+];
+
+fn flag(variable: &str) -> Option<bool> {
+    match std::env::var(variable).ok()?.trim() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// "https://dash.example.com/some/page" -> "https://dash.example.com"
+fn origin_of(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url.trim()).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    })
+}
+
+/// The origins allowed to call the API: `CORS_ALLOWED_ORIGINS` (comma
+/// separated, or `*`), else the origin of `PUBLIC_FRONTEND_URL`. `None`
+/// means any origin, which is what you get with neither set.
+pub fn allowed_origins() -> Option<Vec<String>> {
+    match std::env::var("CORS_ALLOWED_ORIGINS") {
+        Ok(origins) if origins.trim() == "*" => None,
+        Ok(origins) if !origins.trim().is_empty() => {
+            Some(origins.split(',').filter_map(origin_of).collect())
+        }
+        _ => std::env::var("PUBLIC_FRONTEND_URL")
+            .ok()
+            .and_then(|url| origin_of(&url))
+            .map(|origin| vec![origin]),
+    }
+}
+
+fn allowed_methods() -> Vec<Method> {
+    match std::env::var("CORS_ALLOWED_METHODS") {
+        Ok(methods) if !methods.trim().is_empty() => methods
+            .split(',')
+            .filter_map(|method| method.trim().to_ascii_uppercase().parse().ok())
+            .collect(),
+        _ => DEFAULT_METHODS.to_vec(),
+    }
+}
+
+/// Problems with the CORS configuration, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Ok(origins) = std::env::var("CORS_ALLOWED_ORIGINS") {
+        for origin in origins.split(',').map(str::trim) {
+            if !origin.is_empty() && origin != "*" && origin_of(origin).is_none() {
+                problems.push(format!(
+                    "CORS_ALLOWED_ORIGINS has an invalid origin: {}",
+                    origin
+                ));
+            }
+        }
+    }
+    if allowed_origins().is_none() && flag("CORS_ALLOW_CREDENTIALS") == Some(true) {
+        problems.push(
+            "CORS_ALLOW_CREDENTIALS needs explicit CORS_ALLOWED_ORIGINS; credentials are off"
+                .to_string(),
+        );
+    }
+    problems
+}
+
+/// CORS headers per `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS` and
+/// `CORS_ALLOW_CREDENTIALS`. Credentials (session cookies) are allowed by
+/// default whenever the origins are explicit, and never for any origin.
+pub fn layer() -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods(allowed_methods())
+        .expose_headers([
+            header::ETAG,
+            header::RETRY_AFTER,
+            logging::REQUEST_ID_HEADER,
+        ]);
+
+    match allowed_origins() {
+        Some(origins) => {
+            let origins: Vec<HeaderValue> = origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok())
+                .collect();
+            tracing::info!(origins = ?origins, "CORS restricted to configured origins");
+            layer
+                .allow_origin(AllowOrigin::list(origins))
+                .allow_headers(ALLOWED_HEADERS)
+                .allow_credentials(flag("CORS_ALLOW_CREDENTIALS").unwrap_or(true))
+        }
+        None => layer.allow_origin(Any).allow_headers(Any),
+    }
+}
+
+// Same-origin requests carry an Origin too, e.g. when the backend serves the
+// frontend itself
+fn same_origin(request: &Request, origin: &str) -> bool {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    let origin_host = origin.split_once("://").map(|(_, host)| host);
+    host.is_some() && host == origin_host
+}
+
+fn is_protected(request: &Request) -> bool {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    !safe || request.uri().path().starts_with("/api/admin")
+}
+
+/// With `CORS_STRICT=true`, refuses admin requests and anything that writes
+/// when they come from an origin that isn't allowed; with any origin allowed,
+/// only same-origin ones get through. CORS alone only keeps browsers from
+/// reading responses; a cross-site form post still goes through.
+pub async fn enforce_origin(request: Request, next: Next) -> Response {
+    if flag("CORS_STRICT") != Some(true) || !is_protected(&request) {
+        return next.run(request).await;
+    }
+    let Some(origin) = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(str::to_string)
+    else {
+        // Not from a browser, so CORS doesn't apply
+        return next.run(request).await;
+    };
+
+    let allowed = same_origin(&request, &origin)
+        || allowed_origins().is_some_and(|origins| origins.contains(&origin));
+    if allowed {
+        next.run(request).await
+    } else {
+        tracing::info!(origin = %origin, "Refused cross-origin request");
+        (StatusCode::FORBIDDEN, "Cross-origin request not allowed").into_response()
+    }
+}
@@ -0,0 +1,598 @@
+use std::collections::HashSet;
+
+use axum::{Json, extract::Path, http::StatusCode};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::{store, v1::ApiError};
+
+const DASHBOARDS_NAMESPACE: &str = "dashboards";
+// What the page shows without ?dashboard=; built in until someone saves over it
+const DEFAULT_DASHBOARD: &str = "default";
+const MAX_DASHBOARDS: usize = 50;
+const MAX_COLUMNS: u32 = 12;
+const MAX_ROWS: u32 = 24;
+const MAX_WIDGETS: usize = 50;
+const MAX_NAME_LENGTH: usize = 40;
+const MAX_TEXT_LENGTH: usize = 200;
+
+static WRITE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+type DashboardError = (StatusCode, String);
+
+/// What a widget setting holds.
+#[derive(Serialize, ToSchema, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SettingKind {
+    /// A place name to geocode, e.g. "Oslo"
+    Location,
+    /// An IANA timezone, e.g. Europe/Oslo
+    Timezone,
+    /// A BCP 47 language tag, e.g. nb-NO
+    Language,
+    Boolean,
+    /// One of `options`
+    Choice {
+        options: Vec<String>,
+    },
+    /// A list of place ids from `/api/v1/places`
+    Places,
+}
+
+#[derive(Serialize, ToSchema, Clone)]
+pub struct WidgetSetting {
+    pub name: String,
+    pub kind: SettingKind,
+    pub required: bool,
+    pub description: String,
+}
+
+/// A kind of widget the dashboard can show, and the settings it takes.
+#[derive(Serialize, ToSchema, Clone)]
+pub struct WidgetType {
+    #[serde(rename = "type")]
+    pub name: String,
+    pub description: String,
+    pub settings: Vec<WidgetSetting>,
+}
+
+fn setting(name: &str, kind: SettingKind, description: &str) -> WidgetSetting {
+    WidgetSetting {
+        name: name.to_string(),
+        kind,
+        required: false,
+        description: description.to_string(),
+    }
+}
+
+fn units() -> WidgetSetting {
+    setting(
+        "units",
+        SettingKind::Choice {
+            options: vec!["metric".to_string(), "imperial".to_string()],
+        },
+        "Metric if left out",
+    )
+}
+
+fn widget_type(name: &str, description: &str, settings: Vec<WidgetSetting>) -> WidgetType {
+    WidgetType {
+        name: name.to_string(),
+        description: description.to_string(),
+        settings,
+    }
+}
+
+// Every widget the frontend knows how to draw. Add new ones here as well as
+// in the frontend, or dashboards using them are turned away.
+static WIDGET_TYPES: Lazy<Vec<WidgetType>> = Lazy::new(|| {
+    vec![
+        widget_type(
+            "weather",
+            "Current temperature and a written summary",
+            vec![
+                setting(
+                    "location",
+                    SettingKind::Location,
+                    "Where to show; the device asks if left out",
+                ),
+                units(),
+                setting(
+                    "timezone",
+                    SettingKind::Timezone,
+                    "Timezone the summary talks in; the device's own if left out",
+                ),
+            ],
+        ),
+        widget_type(
+            "clock",
+            "Date and time",
+            vec![
+                setting(
+                    "timezone",
+                    SettingKind::Timezone,
+                    "The device's own if left out",
+                ),
+                setting("hour12", SettingKind::Boolean, "12-hour time"),
+                setting(
+                    "language",
+                    SettingKind::Language,
+                    "Language of day and month names; English if left out",
+                ),
+            ],
+        ),
+        widget_type("t3", "Ask t3.chat", vec![]),
+        widget_type(
+            "places",
+            "Current conditions at saved places side by side",
+            vec![
+                setting(
+                    "places",
+                    SettingKind::Places,
+                    "Place ids, in order; all places if left out",
+                ),
+                units(),
+            ],
+        ),
+    ]
+});
+
+/// One widget on the grid. Positions count cells from the top left, from 0.
+#[derive(Deserialize, Serialize, ToSchema, Clone)]
+pub struct Widget {
+    /// Made up when left out
+    #[serde(default)]
+    pub id: String,
+    /// One of the types from `/api/v1/dashboards/widgets`
+    #[serde(rename = "type")]
+    pub widget_type: String,
+    pub x: u32,
+    pub y: u32,
+    /// Width in columns
+    #[serde(default = "one")]
+    pub w: u32,
+    /// Height in rows
+    #[serde(default = "one")]
+    pub h: u32,
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub settings: Map<String, Value>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn default_columns() -> u32 {
+    2
+}
+
+/// A named layout, e.g. for the kitchen tablet. The page shows one with
+/// `?dashboard=<name>`.
+#[derive(Deserialize, Serialize, ToSchema, Clone)]
+pub struct Dashboard {
+    pub name: String,
+    pub title: Option<String>,
+    pub columns: u32,
+    pub widgets: Vec<Widget>,
+    /// Left out for the built-in default
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A layout to save.
+#[derive(Deserialize, ToSchema)]
+pub struct DashboardLayout {
+    #[serde(default)]
+    title: Option<String>,
+    /// Grid columns on wide screens; narrow ones stack widgets
+    #[serde(default = "default_columns")]
+    columns: u32,
+    widgets: Vec<Widget>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewDashboard {
+    /// Lowercase letters, digits and dashes, e.g. kitchen-tablet
+    name: String,
+    #[serde(flatten)]
+    layout: DashboardLayout,
+}
+
+fn internal_error(e: impl std::fmt::Display) -> DashboardError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn invalid(message: impl Into<String>) -> DashboardError {
+    (StatusCode::UNPROCESSABLE_ENTITY, message.into())
+}
+
+fn not_found(name: &str) -> DashboardError {
+    (StatusCode::NOT_FOUND, format!("No dashboard {}", name))
+}
+
+// What the page looked like before dashboards could be configured
+fn default_dashboard() -> Dashboard {
+    let widget = |id: &str, x, y, h| Widget {
+        id: id.to_string(),
+        widget_type: id.to_string(),
+        x,
+        y,
+        w: 1,
+        h,
+        settings: Map::new(),
+    };
+    Dashboard {
+        name: DEFAULT_DASHBOARD.to_string(),
+        title: None,
+        columns: 2,
+        widgets: vec![
+            widget("weather", 0, 0, 2),
+            widget("clock", 1, 0, 1),
+            widget("t3", 1, 1, 1),
+        ],
+        updated_at: None,
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), DashboardError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(invalid(format!(
+            "Names must be 1 to {} lowercase letters, digits or dashes",
+            MAX_NAME_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn validate_text(value: &str, what: &str) -> Result<(), String> {
+    if value.trim().is_empty() || value.chars().count() > MAX_TEXT_LENGTH {
+        return Err(format!(
+            "{} must be 1 to {} characters",
+            what, MAX_TEXT_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+fn validate_language(tag: &str) -> bool {
+    (2..=35).contains(&tag.len())
+        && tag.starts_with(|c: char| c.is_ascii_alphabetic())
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn validate_setting(setting: &WidgetSetting, value: &Value) -> Result<(), String> {
+    let name = &setting.name;
+    match (&setting.kind, value) {
+        (SettingKind::Location, Value::String(text)) => validate_text(text, name),
+        (SettingKind::Timezone, Value::String(timezone)) => timezone
+            .parse::<Tz>()
+            .map(|_| ())
+            .map_err(|_| format!("{} isn't a known timezone: {}", name, timezone)),
+        (SettingKind::Language, Value::String(tag)) if validate_language(tag) => Ok(()),
+        (SettingKind::Boolean, Value::Bool(_)) => Ok(()),
+        (SettingKind::Choice { options }, Value::String(choice)) if options.contains(choice) => {
+            Ok(())
+        }
+        (SettingKind::Choice { options }, _) => {
+            Err(format!("{} must be one of {}", name, options.join(", ")))
+        }
+        (SettingKind::Places, Value::Array(ids)) if ids.iter().all(Value::is_string) => Ok(()),
+        (kind, _) => Err(format!(
+            "{} must be a {}",
+            name,
+            match kind {
+                SettingKind::Location => "string",
+                SettingKind::Timezone => "timezone name",
+                SettingKind::Language => "language tag",
+                SettingKind::Boolean => "boolean",
+                SettingKind::Places => "list of place ids",
+                SettingKind::Choice { .. } => unreachable!(),
+            }
+        )),
+    }
+}
+
+fn validate_widget(widget: &Widget, columns: u32) -> Result<(), String> {
+    let Some(widget_type) = WIDGET_TYPES.iter().find(|t| t.name == widget.widget_type) else {
+        return Err(format!("unknown widget type {}", widget.widget_type));
+    };
+    if widget.w == 0 || widget.h == 0 {
+        return Err("w and h must be at least 1".to_string());
+    }
+    // Checked, as x and y come straight from the request
+    if widget
+        .x
+        .checked_add(widget.w)
+        .is_none_or(|end| end > columns)
+    {
+        return Err(format!("doesn't fit in {} columns", columns));
+    }
+    if widget
+        .y
+        .checked_add(widget.h)
+        .is_none_or(|end| end > MAX_ROWS)
+    {
+        return Err(format!("doesn't fit in {} rows", MAX_ROWS));
+    }
+    for (name, value) in &widget.settings {
+        let Some(setting) = widget_type.settings.iter().find(|s| s.name == *name) else {
+            return Err(format!("{} has no setting {}", widget.widget_type, name));
+        };
+        validate_setting(setting, value)?;
+    }
+    for setting in widget_type.settings.iter().filter(|s| s.required) {
+        if !widget.settings.contains_key(&setting.name) {
+            return Err(format!("{} is required", setting.name));
+        }
+    }
+    Ok(())
+}
+
+// Checks the layout against the widget registry and fills in missing widget
+// ids. Errors name the widget by its position in the list.
+fn validate_layout(layout: &mut DashboardLayout) -> Result<(), DashboardError> {
+    if let Some(title) = &layout.title {
+        validate_text(title, "title").map_err(invalid)?;
+    }
+    if !(1..=MAX_COLUMNS).contains(&layout.columns) {
+        return Err(invalid(format!("columns must be 1 to {}", MAX_COLUMNS)));
+    }
+    if layout.widgets.len() > MAX_WIDGETS {
+        return Err(invalid(format!("At most {} widgets", MAX_WIDGETS)));
+    }
+
+    let mut ids = HashSet::new();
+    let mut cells = HashSet::new();
+    for (index, widget) in layout.widgets.iter_mut().enumerate() {
+        let error = |message: String| invalid(format!("widgets[{}]: {}", index, message));
+        if widget.id.is_empty() {
+            widget.id = format!("{}-{}", widget.widget_type, index);
+        }
+        validate_widget(widget, layout.columns).map_err(error)?;
+        if !ids.insert(widget.id.clone()) {
+            return Err(error(format!("id {} is taken", widget.id)));
+        }
+        for x in widget.x..widget.x + widget.w {
+            for y in widget.y..widget.y + widget.h {
+                if !cells.insert((x, y)) {
+                    return Err(error(format!("overlaps another widget at {},{}", x, y)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn load(name: &str) -> Result<Option<Dashboard>, DashboardError> {
+    let dashboard = store::get(DASHBOARDS_NAMESPACE, name)
+        .await
+        .map_err(internal_error)?;
+    Ok(dashboard.or_else(|| (name == DEFAULT_DASHBOARD).then(default_dashboard)))
+}
+
+async fn save(name: String, mut layout: DashboardLayout) -> Result<Dashboard, DashboardError> {
+    validate_layout(&mut layout)?;
+    let dashboard = Dashboard {
+        name,
+        title: layout.title,
+        columns: layout.columns,
+        widgets: layout.widgets,
+        updated_at: Some(Utc::now()),
+    };
+    store::upsert(
+        DASHBOARDS_NAMESPACE,
+        &dashboard.name,
+        &dashboard,
+        None,
+        None,
+    )
+    .await
+    .map_err(internal_error)?;
+    Ok(dashboard)
+}
+
+/// The widget types dashboards can use, and their settings.
+#[utoipa::path(
+    get,
+    operation_id = "listWidgetTypes",
+    path = "/api/v1/dashboards/widgets",
+    tag = "dashboards",
+    responses((status = 200, body = Vec<WidgetType>))
+)]
+pub async fn handle_widget_types() -> Json<Vec<WidgetType>> {
+    Json(WIDGET_TYPES.clone())
+}
+
+/// Every dashboard by name, including the built-in default.
+#[utoipa::path(
+    get,
+    operation_id = "listDashboards",
+    path = "/api/v1/dashboards",
+    tag = "dashboards",
+    responses(
+        (status = 200, body = Vec<Dashboard>),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_list_dashboards() -> Result<Json<Vec<Dashboard>>, DashboardError> {
+    let mut dashboards: Vec<Dashboard> = store::load_namespace(DASHBOARDS_NAMESPACE)
+        .await
+        .map_err(internal_error)?
+        .into_values()
+        .collect();
+    if !dashboards.iter().any(|d| d.name == DEFAULT_DASHBOARD) {
+        dashboards.push(default_dashboard());
+    }
+    dashboards.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(dashboards))
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "getDashboard",
+    path = "/api/v1/dashboards/{name}",
+    tag = "dashboards",
+    params(("name" = String, Path, description = "Dashboard name, e.g. kitchen-tablet")),
+    responses(
+        (status = 200, body = Dashboard),
+        (status = 404, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_get_dashboard(
+    Path(name): Path<String>,
+) -> Result<Json<Dashboard>, DashboardError> {
+    match load(&name).await? {
+        Some(dashboard) => Ok(Json(dashboard)),
+        None => Err(not_found(&name)),
+    }
+}
+
+/// Adds a dashboard under a name that isn't taken. Needs the write scope.
+#[utoipa::path(
+    post,
+    operation_id = "createDashboard",
+    path = "/api/v1/dashboards",
+    tag = "dashboards",
+    request_body = NewDashboard,
+    responses(
+        (status = 201, body = Dashboard),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 409, response = ApiError),
+        (status = 422, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_create_dashboard(
+    Json(request): Json<NewDashboard>,
+) -> Result<(StatusCode, Json<Dashboard>), DashboardError> {
+    validate_name(&request.name)?;
+    let _guard = WRITE_LOCK.lock().await;
+    let stored = store::entries(DASHBOARDS_NAMESPACE)
+        .await
+        .map_err(internal_error)?;
+    if stored.iter().any(|entry| entry.key == request.name) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("There's already a dashboard {}", request.name),
+        ));
+    }
+    if stored.len() >= MAX_DASHBOARDS {
+        return Err(invalid(format!("At most {} dashboards", MAX_DASHBOARDS)));
+    }
+    let dashboard = save(request.name, request.layout).await?;
+    Ok((StatusCode::CREATED, Json(dashboard)))
+}
+
+/// Replaces a dashboard's layout, creating it if need be. Needs the write
+/// scope.
+#[utoipa::path(
+    put,
+    operation_id = "putDashboard",
+    path = "/api/v1/dashboards/{name}",
+    tag = "dashboards",
+    params(("name" = String, Path, description = "Dashboard name, e.g. kitchen-tablet")),
+    request_body = DashboardLayout,
+    responses(
+        (status = 200, body = Dashboard),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 422, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_put_dashboard(
+    Path(name): Path<String>,
+    Json(layout): Json<DashboardLayout>,
+) -> Result<Json<Dashboard>, DashboardError> {
+    validate_name(&name)?;
+    let _guard = WRITE_LOCK.lock().await;
+    let stored = store::entries(DASHBOARDS_NAMESPACE)
+        .await
+        .map_err(internal_error)?;
+    if !stored.iter().any(|entry| entry.key == name) && stored.len() >= MAX_DASHBOARDS {
+        return Err(invalid(format!("At most {} dashboards", MAX_DASHBOARDS)));
+    }
+    Ok(Json(save(name, layout).await?))
+}
+
+/// Deletes a dashboard. Deleting `default` brings back the built-in one.
+/// Needs the write scope.
+#[utoipa::path(
+    delete,
+    operation_id = "deleteDashboard",
+    path = "/api/v1/dashboards/{name}",
+    tag = "dashboards",
+    params(("name" = String, Path, description = "Dashboard name, e.g. kitchen-tablet")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 404, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_delete_dashboard(
+    Path(name): Path<String>,
+) -> Result<StatusCode, DashboardError> {
+    let _guard = WRITE_LOCK.lock().await;
+    match store::delete(DASHBOARDS_NAMESPACE, &name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(not_found(&name)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(widgets: Value) -> DashboardLayout {
+        serde_json::from_value(serde_json::json!({ "columns": 2, "widgets": widgets })).unwrap()
+    }
+
+    #[test]
+    fn default_dashboard_is_valid() {
+        let default = default_dashboard();
+        let mut layout = DashboardLayout {
+            title: default.title,
+            columns: default.columns,
+            widgets: default.widgets,
+        };
+        assert!(validate_layout(&mut layout).is_ok());
+    }
+
+    #[test]
+    fn rejects_positions_that_overflow() {
+        for widget in [
+            serde_json::json!({ "type": "clock", "x": u32::MAX, "y": 0 }),
+            serde_json::json!({ "type": "clock", "x": 1, "y": 0, "w": u32::MAX }),
+            serde_json::json!({ "type": "clock", "x": 0, "y": u32::MAX }),
+            serde_json::json!({ "type": "clock", "x": 0, "y": 1, "h": u32::MAX }),
+        ] {
+            let mut layout = layout(serde_json::json!([widget]));
+            let (status, _) = validate_layout(&mut layout).unwrap_err();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[test]
+    fn rejects_overlapping_widgets() {
+        let mut layout = layout(serde_json::json!([
+            { "type": "clock", "x": 0, "y": 0, "h": 2 },
+            { "type": "t3", "x": 0, "y": 1 },
+        ]));
+        assert!(validate_layout(&mut layout).is_err());
+    }
+}
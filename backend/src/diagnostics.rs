@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use axum::{Json, http::StatusCode};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    accuracy, air_quality, alerts, auth, cors, frontend,
+    ratelimit::{self, BudgetStatus},
+    shutdown,
+    store::{self, StoreStats},
+    upstream::{self, UpstreamStatus},
+    weather,
+};
+
+static STARTED_AT: Lazy<DateTime<Utc>> = Lazy::new(Utc::now);
+static TASKS: Lazy<Mutex<BTreeMap<&'static str, TaskStatus>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TaskStatus {
+    pub running: bool,
+    pub runs: u64,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    /// Locations (or entries) the last run went through
+    pub last_processed: usize,
+    pub last_failures: usize,
+}
+
+/// Starts the uptime clock.
+pub fn mark_started() {
+    Lazy::force(&STARTED_AT);
+}
+
+pub fn task_started(name: &'static str) {
+    let mut tasks = TASKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let task = tasks.entry(name).or_default();
+    task.running = true;
+    task.last_started = Some(Utc::now());
+}
+
+pub fn task_finished(name: &'static str, processed: usize, failures: usize) {
+    let mut tasks = TASKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let task = tasks.entry(name).or_default();
+    task.running = false;
+    task.runs += 1;
+    task.last_finished = Some(Utc::now());
+    task.last_processed = processed;
+    task.last_failures = failures;
+}
+
+/// Everything the modules find wrong with the configuration.
+pub fn config_problems() -> Vec<String> {
+    [
+        air_quality::config_problems(),
+        accuracy::config_problems(),
+        alerts::config_problems(),
+        auth::config_problems(),
+        cors::config_problems(),
+        frontend::config_problems(),
+        weather::config_problems(),
+    ]
+    .concat()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessCheck {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    ready: bool,
+    checks: Vec<ReadinessCheck>,
+}
+
+fn check(name: &'static str, result: Result<(), String>) -> ReadinessCheck {
+    ReadinessCheck {
+        name,
+        ok: result.is_ok(),
+        detail: result.err(),
+    }
+}
+
+/// The process is up and serving requests.
+#[utoipa::path(
+    get,
+    operation_id = "getHealth",
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, body = String))
+)]
+pub async fn handle_healthz() -> &'static str {
+    "ok"
+}
+
+/// Whether this instance can do its job: storage works and the configuration
+/// is usable. Answers 503 until it can.
+#[utoipa::path(
+    get,
+    operation_id = "getReadiness",
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, body = Readiness),
+        (status = 503, body = Readiness)
+    )
+)]
+pub async fn handle_readyz() -> (StatusCode, Json<Readiness>) {
+    let problems = config_problems();
+    let checks = vec![
+        check(
+            "cache_dir_writable",
+            store::check_writable().map_err(|e| e.to_string()),
+        ),
+        check(
+            "database",
+            store::check_database().await.map_err(|e| e.to_string()),
+        ),
+        check(
+            "accepting_requests",
+            if shutdown::is_shutting_down() {
+                Err("Shutting down".to_string())
+            } else {
+                Ok(())
+            },
+        ),
+        check(
+            "config",
+            if problems.is_empty() {
+                Ok(())
+            } else {
+                Err(problems.join("; "))
+            },
+        ),
+    ];
+
+    let ready = checks.iter().all(|c| c.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks }))
+}
+
+#[derive(Serialize)]
+pub struct Diagnostics {
+    version: &'static str,
+    started_at: DateTime<Utc>,
+    uptime_seconds: i64,
+    store: Option<StoreStats>,
+    config_problems: Vec<String>,
+    upstreams: Vec<UpstreamStatus>,
+    budgets: Vec<BudgetStatus>,
+    tasks: BTreeMap<&'static str, TaskStatus>,
+}
+
+pub async fn handle_diagnostics() -> Json<Diagnostics> {
+    let store = store::stats()
+        .await
+        .inspect_err(|e| tracing::warn!("Failed to read store stats: {}", e))
+        .ok();
+    let tasks = TASKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+
+    Json(Diagnostics {
+        version: env!("CARGO_PKG_VERSION"),
+        started_at: *STARTED_AT,
+        uptime_seconds: Utc::now().signed_duration_since(*STARTED_AT).num_seconds(),
+        store,
+        config_problems: config_problems(),
+        upstreams: upstream::statuses(),
+        budgets: ratelimit::budgets().await,
+        tasks,
+    })
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use async_stream::stream;
+use chrono::Utc;
+use futures_util::Stream;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{location::Coordinates, weather::coord_key};
+
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Weather,
+    Summary,
+    Alerts,
+    AirQuality,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Weather => "weather",
+            EventKind::Summary => "summary",
+            EventKind::Alerts => "alerts",
+            EventKind::AirQuality => "air_quality",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Event {
+    pub id: u64,
+    pub key: String,
+    pub kind: EventKind,
+    /// The new value, already serialized to JSON
+    pub data: String,
+    hash: u64,
+}
+
+static CHANNEL: Lazy<broadcast::Sender<Arc<Event>>> =
+    Lazy::new(|| broadcast::channel(CHANNEL_CAPACITY).0);
+// Ids start from the startup time so they keep growing across restarts and a
+// client's Last-Event-ID from before one still means "older than this"
+static NEXT_ID: Lazy<AtomicU64> =
+    Lazy::new(|| AtomicU64::new(Utc::now().timestamp_millis() as u64));
+static LATEST: Lazy<Mutex<LatestEvents>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// The last event of each kind per location key
+type LatestEvents = HashMap<(String, EventKind), Arc<Event>>;
+
+/// Announces new data for a location. Values identical to the last one
+/// published for the same location and kind are dropped.
+pub fn publish<T: Serialize>(coord: &Coordinates, kind: EventKind, value: &T) {
+    let data = match serde_json::to_string(value) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Failed to serialize {} event: {}", kind.as_str(), e);
+            return;
+        }
+    };
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    let hash = hasher.finish();
+    let key = coord_key(coord);
+
+    let Ok(mut latest) = LATEST.lock() else {
+        return;
+    };
+    if latest
+        .get(&(key.clone(), kind))
+        .is_some_and(|event| event.hash == hash)
+    {
+        return;
+    }
+    let event = Arc::new(Event {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        key: key.clone(),
+        kind,
+        data,
+        hash,
+    });
+    latest.insert((key, kind), event.clone());
+    // Sending only fails when nobody is listening
+    let _ = CHANNEL.send(event);
+}
+
+// The latest event of each kind for the given locations that's newer than
+// `after`, oldest first
+fn latest_since(keys: &[String], after: u64) -> Vec<Arc<Event>> {
+    let Ok(latest) = LATEST.lock() else {
+        return Vec::new();
+    };
+    let mut events: Vec<Arc<Event>> = latest
+        .values()
+        .filter(|event| event.id > after && keys.contains(&event.key))
+        .cloned()
+        .collect();
+    events.sort_by_key(|event| event.id);
+    events
+}
+
+/// Events for the given location keys. Resuming from `last_event_id` replays
+/// the current state of everything that changed since, rather than every
+/// intermediate value.
+pub fn subscribe(keys: Vec<String>, last_event_id: Option<u64>) -> impl Stream<Item = Arc<Event>> {
+    // Subscribe before reading the latest values so nothing falls in between
+    let mut receiver = CHANNEL.subscribe();
+    let mut sent_up_to = last_event_id.unwrap_or(0);
+
+    stream! {
+        for event in latest_since(&keys, sent_up_to) {
+            sent_up_to = sent_up_to.max(event.id);
+            yield event;
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if event.id > sent_up_to && keys.contains(&event.key) {
+                        sent_up_to = event.id;
+                        yield event;
+                    }
+                }
+                // Fell behind; catch up from the latest values instead
+                Err(RecvError::Lagged(_)) => {
+                    for event in latest_since(&keys, sent_up_to) {
+                        sent_up_to = sent_up_to.max(event.id);
+                        yield event;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{HeaderValue, header},
+    middleware::{self, Next},
+    response::Response,
+};
+use tower_http::services::{ServeDir, ServeFile};
+
+/// SvelteKit puts content-hashed build output here, so it never changes.
+const IMMUTABLE_PREFIX: &str = "/_app/immutable/";
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+// Everything else, index.html above all, is revalidated so a new build shows up
+const REVALIDATE_CACHE_CONTROL: &str = "no-cache";
+
+/// The built frontend to serve, from `FRONTEND_DIR`.
+fn frontend_dir() -> Option<PathBuf> {
+    std::env::var("FRONTEND_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+/// Problems with the frontend configuration, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    match frontend_dir() {
+        Some(dir) if !dir.join("index.html").is_file() => {
+            vec![format!("FRONTEND_DIR {} has no index.html", dir.display())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+async fn cache_headers(request: Request, next: Next) -> Response {
+    let immutable = request.uri().path().starts_with(IMMUTABLE_PREFIX);
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        let cache_control = if immutable {
+            IMMUTABLE_CACHE_CONTROL
+        } else {
+            REVALIDATE_CACHE_CONTROL
+        };
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+    }
+    response
+}
+
+/// Serves the frontend build in `FRONTEND_DIR`, if set, for every path no
+/// route claims. Precompressed `.br` and `.gz` siblings are sent to clients
+/// that accept them, and unknown paths get `index.html` so client-side
+/// routing works.
+pub fn router() -> Option<Router> {
+    let dir = frontend_dir()?;
+    tracing::info!("Serving the frontend from {}", dir.display());
+    let index = ServeFile::new(dir.join("index.html"))
+        .precompressed_br()
+        .precompressed_gzip();
+    let files = ServeDir::new(dir)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(index);
+    Some(
+        Router::new()
+            .fallback_service(files)
+            .layer(middleware::from_fn(cache_headers)),
+    )
+}
@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::{
+    accuracy, diagnostics,
+    location::{self, Coordinates},
+    shutdown, store, tracking,
+    weather::{self, PublicWeatherResponse, coord_key},
+};
+
+/// How often the "now" conditions of tracked locations are recorded.
+const RECORD_INTERVAL_SECONDS: i64 = 30 * 60;
+
+#[derive(Serialize, Debug, Clone, Default, ToSchema)]
+pub struct WeatherSnapshot {
+    pub recorded_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub air_temperature: Option<f64>,
+    /// Expected precipitation over the following hour, in mm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation_amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed_of_gust: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_from_direction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub air_pressure_at_sea_level: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_area_fraction: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Raw,
+    #[default]
+    Hour,
+    Day,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct HistoryBucket {
+    pub time: DateTime<Utc>,
+    pub samples: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_max: Option<f64>,
+    /// Estimated from the hourly forecast amounts, in mm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation_amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed_avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed_max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub air_pressure_avg: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct WeatherHistory {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub resolution: Resolution,
+    pub buckets: Vec<HistoryBucket>,
+}
+
+/// The conditions for the current hour of a forecast.
+pub fn snapshot(weather: &PublicWeatherResponse) -> Option<WeatherSnapshot> {
+    let current = &weather.properties.timeseries.first()?.data;
+    let instant = current.instant.details.as_ref()?;
+    Some(WeatherSnapshot {
+        recorded_at: Utc::now(),
+        air_temperature: instant.air_temperature,
+        precipitation_amount: current
+            .next_1_hours
+            .as_ref()
+            .and_then(|f| f.details.as_ref())
+            .and_then(|d| d.precipitation_amount),
+        wind_speed: instant.wind_speed,
+        wind_speed_of_gust: instant.wind_speed_of_gust,
+        wind_from_direction: instant.wind_from_direction,
+        air_pressure_at_sea_level: instant.air_pressure_at_sea_level,
+        relative_humidity: instant.relative_humidity,
+        cloud_area_fraction: instant.cloud_area_fraction,
+    })
+}
+
+// Snapshots are stored per interval slot, so several names for the same place
+// (or a restart mid-interval) can't record duplicates
+async fn record(
+    coord: &Coordinates,
+    snapshot: WeatherSnapshot,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    let slot = snapshot.recorded_at.timestamp() / RECORD_INTERVAL_SECONDS * RECORD_INTERVAL_SECONDS;
+
+    store::with_db(move |conn| {
+        conn.execute(
+            "INSERT OR REPLACE INTO weather_history (
+                coord_key, recorded_at, air_temperature, precipitation_amount, wind_speed,
+                wind_speed_of_gust, wind_from_direction, air_pressure_at_sea_level,
+                relative_humidity, cloud_area_fraction
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                key,
+                slot,
+                snapshot.air_temperature,
+                snapshot.precipitation_amount,
+                snapshot.wind_speed,
+                snapshot.wind_speed_of_gust,
+                snapshot.wind_from_direction,
+                snapshot.air_pressure_at_sea_level,
+                snapshot.relative_humidity,
+                snapshot.cloud_area_fraction,
+            ],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn record_location(location: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let coords = location::get_coordinates(location).await?;
+    let weather_data = weather::fetch_weather(&coords).await?;
+    let snapshot = snapshot(&weather_data).ok_or("No current conditions in forecast")?;
+    record(&coords, snapshot).await?;
+    accuracy::record_predictions(&coords, &weather_data).await
+}
+
+/// Records the conditions of every tracked location once per interval,
+/// until shutdown.
+pub fn spawn_recorder() -> JoinHandle<()> {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(RECORD_INTERVAL_SECONDS as u64));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = shutdown::requested() => break,
+            }
+            diagnostics::task_started("history");
+            let locations = tracking::tracked_locations().await;
+            let mut failures = 0;
+            for location in &locations {
+                if shutdown::is_shutting_down() {
+                    break;
+                }
+                if let Err(e) = record_location(location).await {
+                    tracing::warn!("Failed to record weather history for {}: {}", location, e);
+                    failures += 1;
+                }
+            }
+            diagnostics::task_finished("history", locations.len(), failures);
+        }
+    })
+}
+
+pub async fn load_snapshots(
+    coord: &Coordinates,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<WeatherSnapshot>, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    store::with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT recorded_at, air_temperature, precipitation_amount, wind_speed,
+                    wind_speed_of_gust, wind_from_direction, air_pressure_at_sea_level,
+                    relative_humidity, cloud_area_fraction
+             FROM weather_history
+             WHERE coord_key = ?1 AND recorded_at >= ?2 AND recorded_at < ?3
+             ORDER BY recorded_at",
+        )?;
+        let rows = stmt.query_map(params![key, from.timestamp(), to.timestamp()], |row| {
+            Ok(WeatherSnapshot {
+                recorded_at: DateTime::from_timestamp(row.get(0)?, 0).unwrap_or_default(),
+                air_temperature: row.get(1)?,
+                precipitation_amount: row.get(2)?,
+                wind_speed: row.get(3)?,
+                wind_speed_of_gust: row.get(4)?,
+                wind_from_direction: row.get(5)?,
+                air_pressure_at_sea_level: row.get(6)?,
+                relative_humidity: row.get(7)?,
+                cloud_area_fraction: row.get(8)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    })
+    .await
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn max(values: &[f64]) -> Option<f64> {
+    values.iter().copied().reduce(f64::max)
+}
+
+fn min(values: &[f64]) -> Option<f64> {
+    values.iter().copied().reduce(f64::min)
+}
+
+// Start of the local hour or day a snapshot falls in
+fn bucket_start(time: DateTime<Utc>, resolution: Resolution, tz: &Tz) -> DateTime<Utc> {
+    let local = time.with_timezone(tz);
+    let start = match resolution {
+        Resolution::Raw => return time,
+        Resolution::Hour => local
+            .with_minute(0)
+            .and_then(|t| t.with_second(0))
+            .and_then(|t| t.with_nanosecond(0)),
+        Resolution::Day => local
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(*tz).earliest()),
+    };
+    start.map(|t| t.with_timezone(&Utc)).unwrap_or(time)
+}
+
+fn summarize_bucket(time: DateTime<Utc>, snapshots: &[WeatherSnapshot]) -> HistoryBucket {
+    let collect = |f: fn(&WeatherSnapshot) -> Option<f64>| -> Vec<f64> {
+        snapshots.iter().filter_map(f).collect()
+    };
+    let temperatures = collect(|s| s.air_temperature);
+    let precipitation = collect(|s| s.precipitation_amount);
+    let wind = collect(|s| s.wind_speed);
+    let gusts = collect(|s| s.wind_speed_of_gust.or(s.wind_speed));
+    let pressure = collect(|s| s.air_pressure_at_sea_level);
+
+    // Each snapshot carries the amount expected over the next hour, so the
+    // hourly rate times the hours covered estimates the bucket total
+    let hours_covered = snapshots.len() as f64 * RECORD_INTERVAL_SECONDS as f64 / 3600.0;
+
+    HistoryBucket {
+        time,
+        samples: snapshots.len(),
+        temperature_avg: average(&temperatures),
+        temperature_min: min(&temperatures),
+        temperature_max: max(&temperatures),
+        precipitation_amount: average(&precipitation).map(|rate| rate * hours_covered),
+        wind_speed_avg: average(&wind),
+        wind_speed_max: max(&gusts),
+        air_pressure_avg: average(&pressure),
+    }
+}
+
+pub async fn history_for(
+    coord: &Coordinates,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution: Resolution,
+    tz: &Tz,
+) -> Result<WeatherHistory, Box<dyn std::error::Error + Send + Sync>> {
+    let snapshots = load_snapshots(coord, from, to).await?;
+
+    let mut grouped: BTreeMap<DateTime<Utc>, Vec<WeatherSnapshot>> = BTreeMap::new();
+    for snapshot in snapshots {
+        grouped
+            .entry(bucket_start(snapshot.recorded_at, resolution, tz))
+            .or_default()
+            .push(snapshot);
+    }
+
+    Ok(WeatherHistory {
+        from,
+        to,
+        resolution,
+        buckets: grouped
+            .iter()
+            .map(|(time, snapshots)| summarize_bucket(*time, snapshots))
+            .collect(),
+    })
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Request},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+
+use crate::auth;
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+// Stale validators are dropped once there are this many
+const MAX_VALIDATORS: usize = 10_000;
+
+// The validators of recent cacheable responses by URI, for [`revalidate`]
+static VALIDATORS: Lazy<Mutex<HashMap<String, Validator>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct Validator {
+    etag: String,
+    last_modified: Option<HeaderValue>,
+    // Until when clients were told they may reuse the response
+    fresh_until: Instant,
+}
+
+/// `Cache-Control` and `Last-Modified` for a response built from a cache
+/// entry. Responses carrying these also get an ETag from [`etag`].
+pub struct CacheHeaders {
+    last_modified: Option<DateTime<Utc>>,
+    max_age: i64,
+}
+
+impl CacheHeaders {
+    /// For data fetched at `fetched_at` that is kept for `lifetime_seconds`, so
+    /// clients may reuse it for as long as our cache would.
+    pub fn for_entry(fetched_at: Option<DateTime<Utc>>, lifetime_seconds: i64) -> Self {
+        let max_age = fetched_at.map_or(0, |t| {
+            lifetime_seconds - Utc::now().signed_duration_since(t).num_seconds()
+        });
+        CacheHeaders {
+            last_modified: fetched_at,
+            max_age: max_age.max(0),
+        }
+    }
+
+    pub fn max_age(seconds: i64) -> Self {
+        CacheHeaders {
+            last_modified: None,
+            max_age: seconds,
+        }
+    }
+}
+
+impl IntoResponseParts for CacheHeaders {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let cache_control = if self.max_age > 0 {
+            format!("public, max-age={}", self.max_age)
+        } else {
+            "no-cache".to_string()
+        };
+        if let Ok(value) = HeaderValue::from_str(&cache_control) {
+            res.headers_mut().insert(header::CACHE_CONTROL, value);
+        }
+        if let Some(value) = self
+            .last_modified
+            .and_then(|t| HeaderValue::from_str(&t.format(HTTP_DATE_FORMAT).to_string()).ok())
+        {
+            res.headers_mut().insert(header::LAST_MODIFIED, value);
+        }
+        Ok(res)
+    }
+}
+
+fn has_credentials(request: &Request) -> bool {
+    request.headers().contains_key(header::AUTHORIZATION)
+        || request.headers().contains_key(header::COOKIE)
+        || request
+            .uri()
+            .query()
+            .is_some_and(|query| query.split('&').any(|p| p.starts_with("access_token=")))
+}
+
+/// Turns `Cache-Control: public` into `private` when the response may depend
+/// on who asked: with `AUTH_REQUIRED` set or credentials sent. Otherwise a
+/// shared cache in front, like a tunnel or proxy, could hand protected data
+/// to anyone.
+pub async fn private_when_authenticated(request: Request, next: Next) -> Response {
+    let private = auth::auth_required() || has_credentials(&request);
+    let mut response = next.run(request).await;
+    if !private {
+        return response;
+    }
+    let headers = response.headers_mut();
+    let public = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("public"))
+        .map(|rest| format!("private{}", rest));
+    if let Some(value) = public.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(header::CACHE_CONTROL, value);
+        headers.append(
+            header::VARY,
+            HeaderValue::from_static("Authorization, Cookie"),
+        );
+    }
+    response
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    // Comparison is weak: the compression layer serves several encodings of
+    // the same entity under one tag
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+fn not_modified_since(if_modified_since: &str, last_modified: Option<&HeaderValue>) -> bool {
+    let last_modified = last_modified
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    let since = DateTime::parse_from_rfc2822(if_modified_since).ok();
+    matches!((last_modified, since), (Some(modified), Some(since)) if modified <= since)
+}
+
+// If-None-Match takes precedence over If-Modified-Since when both are sent
+fn not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: &str,
+    last_modified: Option<&HeaderValue>,
+) -> bool {
+    match (if_none_match, if_modified_since) {
+        (Some(if_none_match), _) => matches_etag(if_none_match, etag),
+        (None, Some(since)) => not_modified_since(since, last_modified),
+        (None, None) => false,
+    }
+}
+
+fn max_age(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CACHE_CONTROL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find_map(|directive| directive.trim().strip_prefix("max-age="))?
+        .parse()
+        .ok()
+}
+
+// Responses served from a cache entry are the same for as long as clients
+// may reuse them, so the entry's time and the URI identify the body
+fn entry_etag(uri: &str, last_modified: &HeaderValue) -> String {
+    let mut hasher = DefaultHasher::new();
+    uri.hash(&mut hasher);
+    last_modified.as_bytes().hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+fn remember(uri: String, etag: String, last_modified: Option<HeaderValue>, max_age: u64) {
+    let now = Instant::now();
+    let mut validators = VALIDATORS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if validators.len() >= MAX_VALIDATORS {
+        validators.retain(|_, validator| validator.fresh_until > now);
+        if validators.len() >= MAX_VALIDATORS {
+            validators.clear();
+        }
+    }
+    validators.insert(
+        uri,
+        Validator {
+            etag,
+            last_modified,
+            fresh_until: now + Duration::from_secs(max_age),
+        },
+    );
+}
+
+fn not_modified_response(etag: &str, last_modified: Option<HeaderValue>, max_age: u64) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+    if let Some(value) = last_modified {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&format!("public, max-age={}", max_age)) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+    response
+}
+
+/// Answers a conditional GET with `304 Not Modified` without running the
+/// handler, when it's for a response [`etag`] saw recently that clients may
+/// still reuse. A route layer, so it runs after authentication and rate
+/// limits.
+pub async fn revalidate(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    let if_none_match = header_string(request.headers(), header::IF_NONE_MATCH);
+    let if_modified_since = header_string(request.headers(), header::IF_MODIFIED_SINCE);
+    if if_none_match.is_none() && if_modified_since.is_none() {
+        return next.run(request).await;
+    }
+    // Nested routers see their own part of the path
+    let uri = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.to_string(),
+        None => request.uri().to_string(),
+    };
+
+    let now = Instant::now();
+    let validator = VALIDATORS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&uri)
+        .filter(|validator| validator.fresh_until > now)
+        .map(|validator| {
+            (
+                validator.etag.clone(),
+                validator.last_modified.clone(),
+                validator.fresh_until - now,
+            )
+        });
+    match validator {
+        Some((etag, last_modified, remaining))
+            if not_modified(
+                if_none_match.as_deref(),
+                if_modified_since.as_deref(),
+                &etag,
+                last_modified.as_ref(),
+            ) =>
+        {
+            not_modified_response(&etag, last_modified, remaining.as_secs())
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// Adds an ETag to cacheable GET responses and answers `304 Not Modified`
+/// when the client already has the same body. Responses with a
+/// `Last-Modified` get theirs from the cache entry and URI; others from a
+/// hash of the body.
+pub async fn etag(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    let uri = request.uri().to_string();
+    let if_none_match = header_string(request.headers(), header::IF_NONE_MATCH);
+    let if_modified_since = header_string(request.headers(), header::IF_MODIFIED_SINCE);
+
+    let response = next.run(request).await;
+    // Only responses that opted in with CacheHeaders; this also keeps streams
+    // from being buffered
+    if response.status() != StatusCode::OK
+        || !response.headers().contains_key(header::CACHE_CONTROL)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let last_modified = parts.headers.get(header::LAST_MODIFIED).cloned();
+    let (etag, body) = match &last_modified {
+        Some(last_modified) => (entry_etag(&uri, last_modified), body),
+        None => {
+            let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            };
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            (format!("W/\"{:016x}\"", hasher.finish()), Body::from(bytes))
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(header::ETAG, value);
+    }
+    if let Some(max_age) = max_age(&parts.headers).filter(|&age| age > 0) {
+        remember(uri, etag.clone(), last_modified.clone(), max_age);
+    }
+
+    if not_modified(
+        if_none_match.as_deref(),
+        if_modified_since.as_deref(),
+        &etag,
+        last_modified.as_ref(),
+    ) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, body)
+}
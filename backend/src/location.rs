@@ -2,12 +2,26 @@ use std::collections::HashMap;
 
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::error::Error as StdError;
 use tokio::sync::RwLock;
-
-const LOCATION_CACHE_FILE: &str = "cache/location_cache.json";
-
-#[derive(Deserialize, Serialize, Clone)]
+use utoipa::ToSchema;
+
+use crate::{
+    metrics::{self, CacheResult, TimedSend},
+    store,
+};
+
+// Geocodes used to live in this file in the cache directory; it's imported
+// into the store once
+const LEGACY_LOCATION_CACHE_FILE: &str = "location_cache.json";
+pub const LOCATION_NAMESPACE: &str = "location";
+/// Coordinates pinned by hand, which win over geocoding.
+const OVERRIDE_NAMESPACE: &str = "location_override";
+/// Names Nominatim found nothing for, so they aren't looked up again for a
+/// while, as its usage policy asks.
+const MISS_NAMESPACE: &str = "location_miss";
+const MISS_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 pub struct Coordinates {
     lat: f64,
     lon: f64,
@@ -35,39 +49,108 @@ struct GeocodeResult {
 #[derive(Deserialize, Serialize)]
 struct GeocodeResponse(Vec<GeocodeResult>);
 
-type LocationCache = HashMap<String, Coordinates>;
+pub type LocationCache = HashMap<String, Coordinates>;
 
 static CACHE: Lazy<RwLock<LocationCache>> = Lazy::new(|| RwLock::new(LocationCache::new()));
+static OVERRIDES: Lazy<RwLock<Option<LocationCache>>> = Lazy::new(|| RwLock::new(None));
 
 async fn load_cache() -> Result<LocationCache, Box<dyn std::error::Error + Send + Sync>> {
-    match tokio::fs::read_to_string(LOCATION_CACHE_FILE).await {
+    let cache: LocationCache = store::load_namespace(LOCATION_NAMESPACE).await?;
+    if !cache.is_empty() {
+        return Ok(cache);
+    }
+
+    let legacy_file = store::cache_dir().join(LEGACY_LOCATION_CACHE_FILE);
+    match tokio::fs::read_to_string(&legacy_file).await {
         Ok(data) => {
-            let cache: LocationCache = serde_json::from_str(&data)?;
-            Ok(cache)
+            let legacy: LocationCache = serde_json::from_str(&data)?;
+            for (location, coords) in &legacy {
+                store::upsert(LOCATION_NAMESPACE, location, coords, None, None).await?;
+            }
+            tokio::fs::remove_file(&legacy_file).await?;
+            tracing::info!(
+                "Imported {} locations from the legacy cache file",
+                legacy.len()
+            );
+            Ok(legacy)
         }
-        Err(_) => Ok(LocationCache::new()),
+        Err(_) => Ok(cache),
     }
 }
 
-async fn save_cache(cache: &LocationCache) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let data = serde_json::to_string(cache)?;
-    match tokio::fs::create_dir_all("cache").await {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to create cache directory: {}", e);
-            return Err(Box::new(e));
+/// All pinned coordinate overrides by location name.
+pub async fn overrides() -> Result<LocationCache, Box<dyn std::error::Error + Send + Sync>> {
+    {
+        let overrides = OVERRIDES.read().await;
+        if let Some(overrides) = overrides.as_ref() {
+            return Ok(overrides.clone());
         }
     }
-    tokio::fs::write(LOCATION_CACHE_FILE, data).await?;
+    let mut overrides = OVERRIDES.write().await;
+    let loaded: LocationCache = store::load_namespace(OVERRIDE_NAMESPACE).await?;
+    *overrides = Some(loaded.clone());
+    Ok(loaded)
+}
+
+/// Pins `location` to the given coordinates, whatever the geocoder says.
+pub async fn pin(
+    location: &str,
+    coords: Coordinates,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    overrides().await?;
+    store::upsert(OVERRIDE_NAMESPACE, location, &coords, None, None).await?;
+    if let Some(overrides) = OVERRIDES.write().await.as_mut() {
+        overrides.insert(location.to_string(), coords);
+    }
+    Ok(())
+}
+
+/// Removes a pinned override; returns whether there was one.
+pub async fn unpin(location: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    overrides().await?;
+    let removed = store::delete(OVERRIDE_NAMESPACE, location).await?;
+    if let Some(overrides) = OVERRIDES.write().await.as_mut() {
+        overrides.remove(location);
+    }
+    Ok(removed)
+}
+
+/// Replaces the cached geocode of `location`. Unlike a pin, it's replaced
+/// again if the location is forgotten and geocoded anew.
+pub async fn set_cached(
+    location: &str,
+    coords: Coordinates,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    store::upsert(LOCATION_NAMESPACE, location, &coords, None, None).await?;
+    CACHE.write().await.insert(location.to_string(), coords);
     Ok(())
 }
 
+/// Drops the cached geocode of `location`, or that there wasn't one, so it's
+/// looked up anew; returns whether there was either.
+pub async fn forget(location: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let removed = store::delete(LOCATION_NAMESPACE, location).await?;
+    let missed = store::delete(MISS_NAMESPACE, location).await?;
+    CACHE.write().await.remove(location);
+    Ok(removed || missed)
+}
+
+#[tracing::instrument(skip_all, fields(query = %location))]
 pub async fn get_coordinates(
     location: &str,
 ) -> Result<Coordinates, Box<dyn std::error::Error + Send + Sync>> {
+    match overrides().await {
+        Ok(overrides) => {
+            if let Some(coords) = overrides.get(location) {
+                return Ok(coords.clone());
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load location overrides: {}", e),
+    }
     {
         let cache_read = CACHE.read().await;
         if let Some(coords) = cache_read.get(location) {
+            metrics::cache_lookup("location", CacheResult::Hit);
             return Ok(coords.clone());
         }
     }
@@ -79,14 +162,22 @@ pub async fn get_coordinates(
                 *cache_write = file_cache;
             }
             Err(e) => {
-                eprintln!("Failed to load location cache: {}", e);
+                tracing::warn!("Failed to load location cache: {}", e);
             }
         }
     }
 
     if let Some(coords) = cache_write.get(location) {
+        metrics::cache_lookup("location", CacheResult::Hit);
         return Ok(coords.clone());
     }
+    metrics::cache_lookup("location", CacheResult::Miss);
+
+    match store::get::<bool>(MISS_NAMESPACE, location).await {
+        Ok(Some(_)) => return Err("No results found".into()),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to check geocode misses: {}", e),
+    }
 
     let url = format!(
         "https://nominatim.openstreetmap.org/search?q={}&format=json",
@@ -97,25 +188,31 @@ pub async fn get_coordinates(
     let response = match client
         .get(&url)
         .header("User-Agent", "wreport (eemeliruoh@gmail.com)") // <-- Required!
-        .send()
+        .send_timed()
         .await
     {
         Ok(resp) => match resp.json::<GeocodeResponse>().await {
             Ok(json) => json,
             Err(e) => {
-                eprintln!("Failed to parse geocode JSON response: {:#}", e);
+                tracing::warn!("Failed to parse geocode JSON response: {:#}", e);
                 return Err(Box::new(e));
             }
         },
         Err(e) => {
-            // Print the full error chain to stderr so docker logs capture the root cause
-            eprintln!("Failed to send geocode request for url ({}): {}", url, e);
+            // Log the full error chain so docker logs capture the root cause
+            let mut causes = Vec::new();
             let mut source = e.source();
             while let Some(s) = source {
-                eprintln!("  caused by: {}", s);
+                causes.push(s.to_string());
                 source = s.source();
             }
-            return Err(Box::new(e));
+            tracing::warn!(
+                url = %url,
+                caused_by = %causes.join(": "),
+                "Failed to send geocode request: {}",
+                e
+            );
+            return Err(e);
         }
     };
 
@@ -124,11 +221,17 @@ pub async fn get_coordinates(
         let lon: f64 = result.lon.parse()?;
         let coordinates = Coordinates::new(lat, lon);
         cache_write.insert(location.to_string(), coordinates.clone());
-        if let Err(e) = save_cache(&*cache_write).await {
-            eprintln!("Failed to save location cache: {}", e);
+        if let Err(e) = store::upsert(LOCATION_NAMESPACE, location, &coordinates, None, None).await
+        {
+            tracing::warn!("Failed to save location cache: {}", e);
         }
         Ok(coordinates)
     } else {
+        let expires_at = chrono::Utc::now() + chrono::Duration::seconds(MISS_SECONDS);
+        if let Err(e) = store::upsert(MISS_NAMESPACE, location, &true, Some(expires_at), None).await
+        {
+            tracing::warn!("Failed to save geocode miss: {}", e);
+        }
         Err("No results found".into())
     }
 }
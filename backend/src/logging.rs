@@ -0,0 +1,64 @@
+use axum::{extract::Request, http::HeaderName};
+use reqwest::Url;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+// Query parameters that carry credentials and must never reach the logs
+const SECRET_QUERY_PARAMS: [&str; 4] = ["key", "api_key", "access_token", "token"];
+
+/// Logs to stderr, keeping stdout for command output, filtered by `RUST_LOG`
+/// (default `default_level`). `LOG_FORMAT=json` switches to one JSON object
+/// per line for log shippers.
+pub fn init(default_level: &str) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let json = std::env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+    if json {
+        builder.json().flatten_event(true).init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Replaces the values of secret query parameters in `url`.
+pub fn redact_url(url: &mut Url) {
+    if !url
+        .query_pairs()
+        .any(|(name, _)| SECRET_QUERY_PARAMS.contains(&name.as_ref()))
+    {
+        return;
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if SECRET_QUERY_PARAMS.contains(&name.as_ref()) {
+                "REDACTED".to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+}
+
+/// The span every request is handled in, tagged with its request id and,
+/// once authenticated, who made it.
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %request_id,
+        principal = tracing::field::Empty,
+    )
+}
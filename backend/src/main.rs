@@ -1,133 +1,1005 @@
+use std::{
+    convert::Infallible, future::IntoFuture, net::SocketAddr, process::ExitCode, sync::Arc,
+    time::Duration,
+};
+
+use async_stream::stream;
 use axum::{
-    Json, Router,
-    extract::{Path, Query},
-    http::Method,
-    routing::get,
+    Extension, Json, Router,
+    extract::{
+        Path, Query,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::HeaderMap,
+    middleware,
+    response::{
+        Response,
+        sse::{self, Sse},
+    },
+    routing::{delete, get, post, put},
 };
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::Parser;
+use futures_util::{Stream, StreamExt};
 use reqwest::StatusCode;
-use tower_http::cors::{Any, CorsLayer};
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::CompressionLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{location::Coordinates, weather::PublicWeatherResponse};
+use crate::{
+    accuracy::{AccuracyReport, Observation},
+    air_quality::AirQualityReport,
+    alerts::WeatherAlert,
+    astronomy::AstronomyReport,
+    auth::Principal,
+    events::{Event, EventKind},
+    history::{Resolution, WeatherHistory},
+    http_cache::CacheHeaders,
+    location::Coordinates,
+    openapi::ErrorMessage,
+    uv::UvReport,
+    weather::{MarineForecast, PublicWeatherResponse},
+};
 
+mod accuracy;
+mod admin;
+mod air_quality;
+mod alerts;
+mod astronomy;
+mod auth;
+mod cli;
+mod cors;
+mod dashboards;
+mod diagnostics;
+mod events;
+mod frontend;
+mod history;
+mod http_cache;
 mod location;
+mod logging;
+mod metrics;
+mod openapi;
+mod places;
+mod prefetch;
+mod ratelimit;
+mod shutdown;
+mod store;
+mod tracking;
+mod upstream;
+mod uv;
+mod v1;
 mod weather;
 
 #[tokio::main]
-async fn main() {
-    println!("Hello, world!");
-    dotenv::dotenv().ok();
+async fn main() -> ExitCode {
+    let cli = cli::Cli::parse();
+    if let Err(e) = cli.load_config() {
+        eprintln!("Failed to load config: {}", e);
+        return ExitCode::FAILURE;
+    }
+    store::set_cache_dir(cli.cache_dir());
+
+    match cli.command {
+        None => serve(None).await,
+        Some(cli::Command::Serve { bind }) => serve(bind).await,
+        Some(command) => {
+            // Only problems, so they don't drown the output
+            logging::init("warn");
+            cli::run(command).await
+        }
+    }
+}
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::DELETE])
-        .allow_headers(Any);
+async fn serve(bind: Option<SocketAddr>) -> ExitCode {
+    logging::init("info");
+    let bind = match bind.map_or_else(cli::default_bind, Ok) {
+        Ok(bind) => bind,
+        Err(e) => {
+            tracing::error!("Invalid BIND_ADDRESS: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    diagnostics::mark_started();
+    tracing::info!(version = env!("CARGO_PKG_VERSION"), "Starting home-backend");
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/api/location/{location}", get(handle_location))
-        .route("/api/weather/{location}", get(handle_weather))
+    let tasks = vec![
+        store::spawn_maintenance(),
+        history::spawn_recorder(),
+        prefetch::spawn_scheduler(),
+    ];
+
+    let admin_routes = Router::new()
+        .route("/diagnostics", get(diagnostics::handle_diagnostics))
+        .route("/caches/{cache}", get(admin::handle_list_cache))
+        .route(
+            "/caches/{cache}/{key}",
+            get(admin::handle_get_cache_entry)
+                .put(admin::handle_put_cache_entry)
+                .delete(admin::handle_delete_cache_entry),
+        )
+        .route("/locations/overrides", get(admin::handle_list_overrides))
         .route(
-            "/api/weather/{location}/summary",
-            get(handle_summarize_weather),
+            "/locations/{location}/override",
+            put(admin::handle_put_override).delete(admin::handle_delete_override),
         )
         .route(
-            "/api/weather/{location}/current_temperature",
+            "/locations/{location}/refresh",
+            post(admin::handle_refresh_location),
+        )
+        .route("/users", get(admin::handle_list_users))
+        .route(
+            "/users/{username}",
+            put(admin::handle_put_user).delete(admin::handle_delete_user),
+        )
+        .route_layer(middleware::from_fn(auth::require_admin));
+
+    let auth_routes = Router::new()
+        .route(
+            "/login",
+            post(auth::handle_login).route_layer(middleware::from_fn(ratelimit::limit_login)),
+        )
+        .route("/logout", post(auth::handle_logout))
+        .route("/me", get(auth::handle_me))
+        .route(
+            "/tokens",
+            get(auth::handle_list_tokens).post(auth::handle_create_token),
+        )
+        .route("/tokens/{id}", delete(auth::handle_delete_token));
+
+    let api_routes = Router::new()
+        .route("/location/{location}", get(handle_location))
+        .route("/weather/{location}", get(handle_weather))
+        .route("/weather/batch", get(handle_batch_weather))
+        .route(
+            "/weather/{location}/summary",
+            get(handle_summarize_weather)
+                .route_layer(middleware::from_fn(ratelimit::limit_summary)),
+        )
+        .route("/weather/{location}/alerts", get(handle_weather_alerts))
+        .route("/weather/{location}/uv", get(handle_uv))
+        .route("/weather/{location}/history", get(handle_weather_history))
+        .route("/weather/{location}/accuracy", get(handle_weather_accuracy))
+        .route(
+            "/weather/{location}/observations",
+            post(handle_weather_observation).route_layer(middleware::from_fn(auth::require_write)),
+        )
+        .route(
+            "/weather/{location}/current_temperature",
             get(handle_current_temperature),
         )
-        .layer(cors);
+        .route("/astronomy/{location}", get(handle_astronomy))
+        .route("/air-quality/{location}", get(handle_air_quality))
+        .route("/marine/{location}", get(handle_marine))
+        .route("/stream", get(handle_stream))
+        .route("/stream/ws", get(handle_stream_ws))
+        .nest("/v1", v1::router())
+        .route_layer(middleware::from_fn(http_cache::revalidate))
+        .route_layer(middleware::from_fn(ratelimit::limit_api))
+        .route_layer(middleware::from_fn(auth::require_read))
+        .merge(openapi::router())
+        .nest("/admin", admin_routes)
+        .nest("/auth", auth_routes)
+        // Keeps unknown API paths from falling through to the frontend
+        .fallback(handle_api_not_found);
+
+    let app = Router::new()
+        .route("/healthz", get(diagnostics::handle_healthz))
+        .route("/readyz", get(diagnostics::handle_readyz))
+        .route("/metrics", get(metrics::handle_metrics))
+        .nest("/api", api_routes);
+    let app = match frontend::router() {
+        Some(frontend) => app.merge(frontend),
+        None => app.route("/", get(root)),
+    };
+    let app = app
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::from_fn(v1::json_errors))
+        .layer(middleware::from_fn(auth::authenticate))
+        .layer(middleware::from_fn(http_cache::private_when_authenticated))
+        .layer(middleware::from_fn(http_cache::etag))
+        .layer(CompressionLayer::new())
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(
+                    logging::REQUEST_ID_HEADER,
+                    MakeRequestUuid,
+                ))
+                .layer(TraceLayer::new_for_http().make_span_with(logging::request_span))
+                .layer(PropagateRequestIdLayer::new(logging::REQUEST_ID_HEADER)),
+        )
+        .layer(middleware::from_fn(cors::enforce_origin))
+        .layer(cors::layer());
+
+    let listener = match tokio::net::TcpListener::bind(bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to listen on {}: {}", bind, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("Listening on {}", bind);
+
+    // Stops accepting connections on SIGINT/SIGTERM, then waits for the
+    // in-flight requests
+    let mut server = tokio::spawn(
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown::wait_for_signal())
+        .into_future(),
+    );
+    let stopped = tokio::select! {
+        result = &mut server => Some(result),
+        _ = shutdown::requested() => None,
+    };
+    shutdown::begin();
+
+    let deadline = tokio::time::Instant::now() + shutdown::timeout();
+    let result = match stopped {
+        Some(result) => result,
+        None => match tokio::time::timeout_at(deadline, &mut server).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!("Requests still in flight at the shutdown deadline; dropping them");
+                server.abort();
+                Ok(Ok(()))
+            }
+        },
+    };
+    let status = match result {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) => {
+            tracing::error!("Server failed: {}", e);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            tracing::error!("Server task failed: {}", e);
+            ExitCode::FAILURE
+        }
+    };
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:2001").await.unwrap();
-    println!("Listening on {}", listener.local_addr().unwrap());
+    if tokio::time::timeout_at(deadline, futures_util::future::join_all(tasks))
+        .await
+        .is_err()
+    {
+        tracing::warn!("Background tasks still running at the shutdown deadline");
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    tracking::flush().await;
+    if let Err(e) = store::checkpoint().await {
+        tracing::warn!("Failed to checkpoint the database: {}", e);
+    }
+    tracing::info!("Shut down");
+    status
 }
 
 async fn root() -> &'static str {
     "Hello, World!"
 }
 
+async fn handle_api_not_found() -> StatusCode {
+    StatusCode::NOT_FOUND
+}
+
+// A JSON body with the caching headers of the entry it came from
+type CachedJson<T> = (CacheHeaders, Json<T>);
+
+// Place names don't move, but give a changed geocoder result a chance
+const LOCATION_MAX_AGE_SECONDS: i64 = 24 * 60 * 60;
+
+/// Coordinates of a place name.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetLocation",
+    path = "/api/location/{location}",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Coordinates),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
 async fn handle_location(
     Path(location): Path<String>,
-) -> Result<Json<Coordinates>, (StatusCode, String)> {
+) -> Result<CachedJson<Coordinates>, (StatusCode, String)> {
     match location::get_coordinates(&location).await {
-        Ok(coords) => Ok(Json(coords)),
+        Ok(coords) => Ok((
+            CacheHeaders::max_age(LOCATION_MAX_AGE_SECONDS),
+            Json(coords),
+        )),
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
 }
 
+/// Forecast, as met.no sends it.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetWeather",
+    path = "/api/weather/{location}",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = PublicWeatherResponse),
+        (status = 500, response = ErrorMessage)
+    )
+)]
 #[axum::debug_handler]
+#[tracing::instrument(skip_all, fields(location = %location))]
 async fn handle_weather(
     Path(location): Path<String>,
-) -> Result<Json<PublicWeatherResponse>, (StatusCode, String)> {
+) -> Result<CachedJson<PublicWeatherResponse>, (StatusCode, String)> {
+    // Only names that geocode are tracked, so typos don't get prefetched
     match location::get_coordinates(&location).await {
-        Ok(coords) => match weather::fetch_weather(&coords).await {
-            Ok(weather_data) => Ok(Json(weather_data)),
-            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        },
+        Ok(coords) => {
+            tracking::touch(&location, None).await;
+            match weather::fetch_weather(&coords).await {
+                Ok(weather_data) => Ok((weather_cache_headers(&coords).await, Json(weather_data))),
+                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+            }
+        }
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
 }
 
-#[derive(serde::Serialize)]
+/// Current conditions at several saved places, the same as
+/// `/api/v1/weather/batch`.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetPlacesWeather",
+    path = "/api/weather/batch",
+    tag = "legacy",
+    params(places::BatchQuery),
+    responses(
+        (status = 200, body = Vec<places::PlaceWeather>),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+async fn handle_batch_weather(
+    principal: Option<Extension<Principal>>,
+    query: Query<places::BatchQuery>,
+) -> Result<Json<Vec<places::PlaceWeather>>, (StatusCode, String)> {
+    places::handle_batch_weather(principal, query).await
+}
+
+async fn weather_cache_headers(coords: &Coordinates) -> CacheHeaders {
+    CacheHeaders::for_entry(
+        weather::weather_cached_at(coords).await,
+        weather::CACHE_SECONDS,
+    )
+}
+
+#[derive(serde::Serialize, ToSchema)]
 struct WeatherSummary {
     summary: String,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct WeatherQuery {
+    /// IANA timezone to talk in, e.g. Europe/Oslo; UTC if left out
     #[serde(default)]
     timezone: Option<String>,
 }
 
+/// Short written summary of the forecast and active alerts.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetWeatherSummary",
+    path = "/api/weather/{location}/summary",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), WeatherQuery),
+    responses(
+        (status = 200, body = WeatherSummary),
+        (status = 400, response = ErrorMessage),
+        (status = 429, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
 #[axum::debug_handler]
+#[tracing::instrument(skip_all, fields(location = %location))]
 async fn handle_summarize_weather(
     Path(location): Path<String>,
     Query(params): Query<WeatherQuery>,
-) -> Result<Json<WeatherSummary>, (StatusCode, String)> {
+) -> Result<CachedJson<WeatherSummary>, (StatusCode, String)> {
     match location::get_coordinates(&location).await {
-        Ok(coords) => match weather::fetch_weather(&coords).await {
-            Ok(weather_data) => {
-                match weather::summarize_weather(&weather_data, params.timezone).await {
-                    Ok(summary) => Ok(Json(WeatherSummary { summary })),
-                    Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(coords) => {
+            tracking::touch(&location, params.timezone.as_deref()).await;
+            match weather::fetch_weather(&coords).await {
+                Ok(weather_data) => {
+                    // A failing alert feed shouldn't take the summary down with it
+                    let active_alerts = alerts::alerts_for(&coords).await.unwrap_or_else(|e| {
+                        tracing::warn!("Failed to fetch weather alerts: {}", e);
+                        Vec::new()
+                    });
+                    match weather::summarize_weather(&weather_data, &active_alerts, params.timezone)
+                        .await
+                    {
+                        Ok(summary) => {
+                            let summary = WeatherSummary { summary };
+                            events::publish(&coords, EventKind::Summary, &summary);
+                            let cached_at = weather::summary_cached_at(&weather_data).await;
+                            Ok((
+                                CacheHeaders::for_entry(cached_at, weather::CACHE_SECONDS),
+                                Json(summary),
+                            ))
+                        }
+                        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+                    }
                 }
+                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
             }
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Active weather alerts, most severe first.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetWeatherAlerts",
+    path = "/api/weather/{location}/alerts",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Vec<WeatherAlert>),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_weather_alerts(
+    Path(location): Path<String>,
+) -> Result<CachedJson<Vec<WeatherAlert>>, (StatusCode, String)> {
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match alerts::alerts_for(&coords).await {
+            Ok(active_alerts) => Ok((
+                CacheHeaders::for_entry(alerts::cached_at().await, alerts::ALERT_CACHE_SECONDS),
+                Json(active_alerts),
+            )),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// UV index by hour and when to protect yourself.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetUv",
+    path = "/api/weather/{location}/uv",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), WeatherQuery),
+    responses(
+        (status = 200, body = UvReport),
+        (status = 400, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_uv(
+    Path(location): Path<String>,
+    Query(params): Query<WeatherQuery>,
+) -> Result<CachedJson<UvReport>, (StatusCode, String)> {
+    let tz = parse_timezone(params.timezone)?;
+
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match weather::fetch_weather(&coords).await {
+            Ok(weather_data) => Ok((
+                weather_cache_headers(&coords).await,
+                Json(uv::uv_report(&weather_data, &tz)),
+            )),
             Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
         },
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, ToSchema)]
 struct CurrentTemperature {
     temperature_celsius: f64,
 }
 
+/// Air temperature right now.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetCurrentTemperature",
+    path = "/api/weather/{location}/current_temperature",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = CurrentTemperature),
+        (status = 404, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
 async fn handle_current_temperature(
     Path(location): Path<String>,
-) -> Result<Json<CurrentTemperature>, (StatusCode, String)> {
+) -> Result<CachedJson<CurrentTemperature>, (StatusCode, String)> {
     match location::get_coordinates(&location).await {
-        Ok(coords) => match weather::fetch_weather(&coords).await {
-            Ok(weather_data) => {
-                let current_temp = weather_data
-                    .properties
-                    .timeseries
-                    .first()
-                    .and_then(|ts| ts.data.instant.details.as_ref())
-                    .and_then(|details| details.air_temperature);
-
-                match current_temp {
-                    Some(temp) => Ok(Json(CurrentTemperature {
-                        temperature_celsius: temp,
-                    })),
-                    None => Err((
-                        StatusCode::NOT_FOUND,
-                        "Temperature data not found".to_string(),
-                    )),
+        Ok(coords) => {
+            tracking::touch(&location, None).await;
+            match weather::fetch_weather(&coords).await {
+                Ok(weather_data) => {
+                    let current_temp = weather_data
+                        .properties
+                        .timeseries
+                        .first()
+                        .and_then(|ts| ts.data.instant.details.as_ref())
+                        .and_then(|details| details.air_temperature);
+
+                    match current_temp {
+                        Some(temp) => Ok((
+                            weather_cache_headers(&coords).await,
+                            Json(CurrentTemperature {
+                                temperature_celsius: temp,
+                            }),
+                        )),
+                        None => Err((
+                            StatusCode::NOT_FOUND,
+                            "Temperature data not found".to_string(),
+                        )),
+                    }
                 }
+                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
             }
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AstronomyQuery {
+    /// Day as YYYY-MM-DD; today if left out
+    #[serde(default)]
+    date: Option<String>,
+    /// IANA timezone of the day and the times; UTC if left out
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+/// Sun and moon times for a day.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetAstronomy",
+    path = "/api/astronomy/{location}",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), AstronomyQuery),
+    responses(
+        (status = 200, body = AstronomyReport),
+        (status = 400, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_astronomy(
+    Path(location): Path<String>,
+    Query(params): Query<AstronomyQuery>,
+) -> Result<Json<AstronomyReport>, (StatusCode, String)> {
+    let tz = parse_timezone(params.timezone)?;
+    let date = match params.date {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid date: {}", e)))?,
+        None => Utc::now().with_timezone(&tz).date_naive(),
+    };
+
+    match location::get_coordinates(&location).await {
+        Ok(coords) => Ok(Json(astronomy::astronomy_for(&coords, date, tz))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Air quality and pollen forecast.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetAirQuality",
+    path = "/api/air-quality/{location}",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = AirQualityReport),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_air_quality(
+    Path(location): Path<String>,
+) -> Result<CachedJson<AirQualityReport>, (StatusCode, String)> {
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match air_quality::fetch_air_quality(&coords).await {
+            Ok(report) => Ok((
+                CacheHeaders::for_entry(
+                    air_quality::cached_at(&coords).await,
+                    air_quality::AIR_QUALITY_CACHE_SECONDS,
+                ),
+                Json(report),
+            )),
             Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
         },
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
 }
+
+/// Waves, currents and sea temperature.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetMarine",
+    path = "/api/marine/{location}",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = MarineForecast),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_marine(
+    Path(location): Path<String>,
+) -> Result<CachedJson<MarineForecast>, (StatusCode, String)> {
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match weather::fetch_ocean_forecast(&coords).await {
+            Ok(forecast) => Ok((
+                CacheHeaders::for_entry(
+                    weather::ocean_cached_at(&coords).await,
+                    weather::CACHE_SECONDS,
+                ),
+                Json(forecast),
+            )),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+fn parse_timezone(timezone: Option<String>) -> Result<Tz, (StatusCode, String)> {
+    match timezone {
+        Some(timezone) => timezone
+            .parse()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid timezone: {}", e))),
+        None => Ok(chrono_tz::UTC),
+    }
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    /// RFC 3339 time or YYYY-MM-DD; a day before `to` if left out
+    #[serde(default)]
+    from: Option<String>,
+    /// RFC 3339 time or YYYY-MM-DD; now if left out
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    resolution: Resolution,
+    /// IANA timezone that plain dates and daily buckets are in; UTC if left out
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+// Accepts RFC 3339 timestamps or plain dates, which mean local midnight
+fn parse_history_time(value: &str, tz: &Tz) -> Result<DateTime<Utc>, (StatusCode, String)> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| midnight.and_local_timezone(*tz).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or((StatusCode::BAD_REQUEST, format!("Invalid time: {}", value)))
+}
+
+/// Recorded conditions over a time range.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetWeatherHistory",
+    path = "/api/weather/{location}/history",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), HistoryQuery),
+    responses(
+        (status = 200, body = WeatherHistory),
+        (status = 400, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_weather_history(
+    Path(location): Path<String>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<WeatherHistory>, (StatusCode, String)> {
+    let tz = parse_timezone(params.timezone)?;
+    let to = match params.to {
+        Some(to) => parse_history_time(&to, &tz)?,
+        None => Utc::now(),
+    };
+    let from = match params.from {
+        Some(from) => parse_history_time(&from, &tz)?,
+        None => to - chrono::Duration::days(1),
+    };
+    if from >= to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`from` must be before `to`".to_string(),
+        ));
+    }
+
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match history::history_for(&coords, from, to, params.resolution, &tz).await {
+            Ok(history) => Ok(Json(history)),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AccuracyQuery {
+    /// How many days back to compare, 1 to 365
+    #[serde(default = "default_accuracy_days")]
+    #[param(default = 30, minimum = 1, maximum = 365)]
+    days: i64,
+}
+
+fn default_accuracy_days() -> i64 {
+    30
+}
+
+/// How well the forecasts matched what happened.
+#[utoipa::path(
+    get,
+    operation_id = "legacyGetWeatherAccuracy",
+    path = "/api/weather/{location}/accuracy",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), AccuracyQuery),
+    responses(
+        (status = 200, body = AccuracyReport),
+        (status = 400, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_weather_accuracy(
+    Path(location): Path<String>,
+    Query(params): Query<AccuracyQuery>,
+) -> Result<Json<AccuracyReport>, (StatusCode, String)> {
+    if !(1..=365).contains(&params.days) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`days` must be between 1 and 365".to_string(),
+        ));
+    }
+
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match accuracy::accuracy_for(&coords, params.days).await {
+            Ok(report) => Ok(Json(report)),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Records a measurement to verify forecasts against. Needs the write scope.
+#[utoipa::path(
+    post,
+    operation_id = "legacyRecordObservation",
+    path = "/api/weather/{location}/observations",
+    tag = "legacy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    request_body = Observation,
+    responses(
+        (status = 204, description = "Recorded"),
+        (status = 401, response = ErrorMessage),
+        (status = 403, response = ErrorMessage),
+        (status = 500, response = ErrorMessage)
+    )
+)]
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn handle_weather_observation(
+    Path(location): Path<String>,
+    Json(observation): Json<Observation>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match location::get_coordinates(&location).await {
+        Ok(coords) => match accuracy::record_observation(&coords, observation).await {
+            Ok(()) => Ok(StatusCode::NO_CONTENT),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+const STREAM_HEARTBEAT: Duration = Duration::from_secs(15);
+const MAX_STREAM_LOCATIONS: usize = 20;
+
+#[derive(serde::Deserialize)]
+struct StreamQuery {
+    locations: String,
+    #[serde(default)]
+    timezone: Option<String>,
+    /// For WebSocket clients, which can't set `Last-Event-ID`
+    #[serde(default)]
+    last_event_id: Option<u64>,
+}
+
+enum StreamMessage {
+    Update { location: String, event: Arc<Event> },
+    Heartbeat,
+}
+
+impl StreamMessage {
+    fn to_json(&self) -> String {
+        match self {
+            StreamMessage::Update { location, event } => format!(
+                r#"{{"id":{},"event":"{}","location":{},"data":{}}}"#,
+                event.id,
+                event.kind.as_str(),
+                serde_json::Value::from(location.as_str()),
+                event.data
+            ),
+            StreamMessage::Heartbeat => format!(
+                r#"{{"event":"heartbeat","time":"{}"}}"#,
+                Utc::now().to_rfc3339()
+            ),
+        }
+    }
+}
+
+// Pairs each requested location with the coordinate key its events are
+// published under
+async fn stream_locations(locations: &str) -> Result<Vec<(String, String)>, (StatusCode, String)> {
+    let names: Vec<&str> = locations
+        .split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if names.is_empty() || names.len() > MAX_STREAM_LOCATIONS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Give between 1 and {} locations", MAX_STREAM_LOCATIONS),
+        ));
+    }
+
+    let mut resolved = Vec::new();
+    for name in names {
+        match location::get_coordinates(name).await {
+            Ok(coords) => resolved.push((name.to_string(), weather::coord_key(&coords))),
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        }
+    }
+    Ok(resolved)
+}
+
+// Takes locations from stream_locations, which have all been geocoded, so
+// tracking them can't pick up junk names
+fn stream_messages(
+    locations: Vec<(String, String)>,
+    timezone: Option<String>,
+    last_event_id: Option<u64>,
+) -> impl Stream<Item = StreamMessage> {
+    for (location, _) in &locations {
+        let location = location.clone();
+        let timezone = timezone.clone();
+        tokio::spawn(async move {
+            tracking::touch(&location, timezone.as_deref()).await;
+            if let Err(e) = prefetch::warm(&location, timezone).await {
+                tracing::warn!("Failed to warm {} for streaming: {}", location, e);
+            }
+        });
+    }
+    let keys = locations.iter().map(|(_, key)| key.clone()).collect();
+    let mut updates = Box::pin(events::subscribe(keys, last_event_id));
+
+    stream! {
+        let mut heartbeat = tokio::time::interval(STREAM_HEARTBEAT);
+        heartbeat.tick().await;
+        loop {
+            let update = tokio::select! {
+                update = updates.next() => match update {
+                    Some(event) => Some(event),
+                    None => break,
+                },
+                _ = heartbeat.tick() => None,
+                // Clients reconnect, to whichever instance comes up next
+                _ = shutdown::requested() => break,
+            };
+
+            match update {
+                Some(event) => {
+                    for (location, key) in &locations {
+                        if *key == event.key {
+                            yield StreamMessage::Update {
+                                location: location.clone(),
+                                event: event.clone(),
+                            };
+                        }
+                    }
+                }
+                None => {
+                    // Someone watching counts as viewing, so the prefetcher
+                    // keeps these locations fresh
+                    for (location, _) in &locations {
+                        tracking::touch(location, None).await;
+                    }
+                    yield StreamMessage::Heartbeat;
+                }
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn handle_stream(
+    Query(params): Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, (StatusCode, String)> {
+    parse_timezone(params.timezone.clone())?;
+    let locations = stream_locations(&params.locations).await?;
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        .or(params.last_event_id);
+
+    let messages = stream_messages(locations, params.timezone, last_event_id);
+    Ok(Sse::new(messages.map(|message| {
+        Ok(match &message {
+            StreamMessage::Update { location, event } => sse::Event::default()
+                .id(event.id.to_string())
+                .event(event.kind.as_str())
+                .data(format!(
+                    r#"{{"location":{},"data":{}}}"#,
+                    serde_json::Value::from(location.as_str()),
+                    event.data
+                )),
+            StreamMessage::Heartbeat => sse::Event::default()
+                .event("heartbeat")
+                .data(Utc::now().to_rfc3339()),
+        })
+    })))
+}
+
+#[tracing::instrument(skip_all)]
+async fn handle_stream_ws(
+    ws: WebSocketUpgrade,
+    Query(params): Query<StreamQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    parse_timezone(params.timezone.clone())?;
+    let locations = stream_locations(&params.locations).await?;
+    let messages = stream_messages(locations, params.timezone, params.last_event_id);
+    Ok(ws.on_upgrade(move |socket| stream_websocket(socket, messages)))
+}
+
+async fn stream_websocket(mut socket: WebSocket, messages: impl Stream<Item = StreamMessage>) {
+    let mut messages = Box::pin(messages);
+    loop {
+        tokio::select! {
+            message = messages.next() => {
+                let Some(message) = message else {
+                    if shutdown::is_shutting_down() {
+                        let close = CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "Server shutting down".into(),
+                        };
+                        let _ = socket.send(Message::Close(Some(close))).await;
+                    }
+                    break;
+                };
+                if socket.send(Message::Text(message.to_json().into())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => (),
+            },
+        }
+    }
+}
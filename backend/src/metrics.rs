@@ -0,0 +1,262 @@
+use std::{future::Future, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    CounterVec, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use reqwest::StatusCode;
+use tracing::Instrument;
+
+use crate::{logging, ratelimit, upstream};
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    if let Err(e) = REGISTRY.register(Box::new(collector.clone())) {
+        tracing::warn!("Failed to register metric: {}", e);
+    }
+    collector
+}
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["route", "method", "status"],
+        )
+        .unwrap(),
+    )
+});
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["route", "method", "status"],
+        )
+        .unwrap(),
+    )
+});
+static HTTP_REQUESTS_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("http_requests_in_flight", "HTTP requests being handled"),
+            &["route"],
+        )
+        .unwrap(),
+    )
+});
+static CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("cache_lookups_total", "Cache lookups by outcome"),
+            &["cache", "result"],
+        )
+        .unwrap(),
+    )
+});
+static UPSTREAM_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Latency of requests to upstream APIs",
+            ),
+            &["host"],
+        )
+        .unwrap(),
+    )
+});
+static UPSTREAM_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "upstream_errors_total",
+                "Failed requests and error statuses from upstream APIs",
+            ),
+            &["host"],
+        )
+        .unwrap(),
+    )
+});
+static LLM_TOKENS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("llm_tokens_total", "Tokens used for summaries"),
+            &["model", "kind"],
+        )
+        .unwrap(),
+    )
+});
+static LLM_COST: Lazy<CounterVec> = Lazy::new(|| {
+    register(
+        CounterVec::new(
+            Opts::new(
+                "llm_cost_usd_total",
+                "Estimated summary cost from LLM_INPUT_PRICE_PER_MTOK and LLM_OUTPUT_PRICE_PER_MTOK",
+            ),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+#[derive(Debug, Clone, Copy)]
+pub enum CacheResult {
+    Hit,
+    Miss,
+    /// An entry was there but had expired
+    Stale,
+}
+
+pub fn cache_lookup(cache: &str, result: CacheResult) {
+    let result = match result {
+        CacheResult::Hit => "hit",
+        CacheResult::Miss => "miss",
+        CacheResult::Stale => "stale",
+    };
+    CACHE_LOOKUPS.with_label_values(&[cache, result]).inc();
+    tracing::debug!(cache, result, "cache lookup");
+}
+
+fn price_per_mtok(var: &str) -> f64 {
+    std::env::var(var)
+        .ok()
+        .and_then(|price| price.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Counts the tokens of one LLM call and what they cost.
+pub fn llm_usage(model: &str, prompt_tokens: u64, completion_tokens: u64) {
+    LLM_TOKENS
+        .with_label_values(&[model, "prompt"])
+        .inc_by(prompt_tokens);
+    LLM_TOKENS
+        .with_label_values(&[model, "completion"])
+        .inc_by(completion_tokens);
+
+    let cost = prompt_tokens as f64 * price_per_mtok("LLM_INPUT_PRICE_PER_MTOK") / 1e6
+        + completion_tokens as f64 * price_per_mtok("LLM_OUTPUT_PRICE_PER_MTOK") / 1e6;
+    LLM_COST.with_label_values(&[model]).inc_by(cost);
+}
+
+type UpstreamResult = Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>>;
+
+/// `RequestBuilder::send` that records latency and errors per upstream host,
+/// and fails fast while the host's circuit breaker is open.
+pub trait TimedSend {
+    fn send_timed(self) -> impl Future<Output = UpstreamResult> + Send;
+}
+
+impl TimedSend for reqwest::RequestBuilder {
+    async fn send_timed(self) -> UpstreamResult {
+        let (client, request) = self.build_split();
+        let request = request?;
+        let host = request.url().host_str().unwrap_or("unknown").to_string();
+        // Calls the breaker turns away don't count against the host's budget
+        upstream::allow(&host)?;
+        if let Err(e) = ratelimit::throttle_upstream(&host).await {
+            upstream::cancel_trial(&host);
+            return Err(e.into());
+        }
+        let mut logged_url = request.url().clone();
+        logging::redact_url(&mut logged_url);
+        let span = tracing::info_span!("upstream", host = %host, url = %logged_url);
+
+        let started = Instant::now();
+        let result = client.execute(request).instrument(span.clone()).await;
+        let elapsed = started.elapsed();
+        UPSTREAM_DURATION
+            .with_label_values(&[host.as_str()])
+            .observe(elapsed.as_secs_f64());
+
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                if !status.is_success() {
+                    UPSTREAM_ERRORS.with_label_values(&[host.as_str()]).inc();
+                }
+                // A 4xx is about our request, not the host being down
+                if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                    upstream::record_failure(&host, format!("HTTP {}", status));
+                } else {
+                    upstream::record_success(&host);
+                }
+                span.in_scope(|| {
+                    tracing::debug!(
+                        status = status.as_u16(),
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        "upstream response"
+                    )
+                });
+                Ok(response)
+            }
+            // The error carries the request URL into whatever logs it later
+            Err(mut e) => {
+                if let Some(url) = e.url_mut() {
+                    logging::redact_url(url);
+                }
+                UPSTREAM_ERRORS.with_label_values(&[host.as_str()]).inc();
+                upstream::record_failure(&host, e.to_string());
+                span.in_scope(|| tracing::warn!(error = %e, "upstream request failed"));
+                Err(e.into())
+            }
+        }
+    }
+}
+
+// Counts a request as in flight until dropped, which also happens when the
+// client goes away and the request future is dropped halfway
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn start(route: &str) -> Self {
+        let gauge = HTTP_REQUESTS_IN_FLIGHT.with_label_values(&[route]);
+        gauge.inc();
+        InFlight(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Records count, latency and concurrency of each request per matched route.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let method = request.method().to_string();
+
+    let in_flight = InFlight::start(&route);
+    let started = Instant::now();
+    let response = next.run(request).await;
+    drop(in_flight);
+
+    let status = response.status().as_u16().to_string();
+    let labels = [route.as_str(), method.as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+pub async fn handle_metrics() -> Response {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    match encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        Ok(()) => ([(header::CONTENT_TYPE, encoder.format_type())], buffer).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
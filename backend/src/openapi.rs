@@ -0,0 +1,131 @@
+use axum::{Json, Router, routing::get};
+use utoipa::{
+    Modify, OpenApi, ToResponse,
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+use utoipa_scalar::{Scalar, Servable};
+
+use crate::{auth, dashboards, diagnostics, places, v1};
+
+/// Errors are a plain-text message; the status says what kind.
+#[derive(ToResponse)]
+#[response(description = "What went wrong", content_type = "text/plain")]
+pub struct ErrorMessage(#[allow(dead_code)] String);
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("session"))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Home dashboard API",
+        description = "Weather and more for the home dashboard. Build on `/api/v1`; the routes \
+            tagged legacy pass provider data through as is and may change with it. With \
+            `AUTH_REQUIRED` set, everything but `/api/auth/login` needs a bearer token or \
+            session cookie, which may also come as an `access_token` query parameter. \
+            Responses that come from a cache carry `ETag` and `Cache-Control`, and 429s carry \
+            `Retry-After`."
+    ),
+    paths(
+        v1::handle_location,
+        v1::handle_forecast,
+        v1::handle_current,
+        v1::handle_summary,
+        v1::handle_alerts,
+        v1::handle_uv,
+        v1::handle_history,
+        v1::handle_accuracy,
+        v1::handle_observation,
+        v1::handle_astronomy,
+        v1::handle_air_quality,
+        v1::handle_marine,
+        places::handle_list_places,
+        places::handle_get_place,
+        places::handle_create_place,
+        places::handle_update_place,
+        places::handle_delete_place,
+        places::handle_reorder_places,
+        places::handle_batch_weather,
+        dashboards::handle_widget_types,
+        dashboards::handle_list_dashboards,
+        dashboards::handle_get_dashboard,
+        dashboards::handle_create_dashboard,
+        dashboards::handle_put_dashboard,
+        dashboards::handle_delete_dashboard,
+        crate::handle_location,
+        crate::handle_weather,
+        crate::handle_batch_weather,
+        crate::handle_summarize_weather,
+        crate::handle_weather_alerts,
+        crate::handle_uv,
+        crate::handle_weather_history,
+        crate::handle_weather_accuracy,
+        crate::handle_weather_observation,
+        crate::handle_current_temperature,
+        crate::handle_astronomy,
+        crate::handle_air_quality,
+        crate::handle_marine,
+        auth::handle_login,
+        auth::handle_logout,
+        auth::handle_me,
+        auth::handle_list_tokens,
+        auth::handle_create_token,
+        auth::handle_delete_token,
+        diagnostics::handle_healthz,
+        diagnostics::handle_readyz,
+    ),
+    components(responses(ErrorMessage, v1::ApiError)),
+    modifiers(&SecuritySchemes),
+    security((), ("token" = []), ("session" = []))
+)]
+struct ApiDoc;
+
+async fn handle_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The OpenAPI document at `/openapi.json` and an interactive viewer at
+/// `/docs`, to nest under `/api`. The admin API isn't described.
+pub fn router() -> Router {
+    Router::new()
+        .route("/openapi.json", get(handle_openapi))
+        .merge(Scalar::with_url("/docs", ApiDoc::openapi()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKED_IN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    // Changes to the API show up in review as changes to openapi.json. After
+    // changing the API on purpose, update it with
+    // `UPDATE_OPENAPI=1 cargo test openapi`.
+    #[test]
+    fn openapi_json_is_up_to_date() {
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(CHECKED_IN, &generated).unwrap();
+            return;
+        }
+        let checked_in = std::fs::read_to_string(CHECKED_IN).unwrap_or_default();
+        assert!(
+            generated == checked_in,
+            "The API no longer matches openapi.json; run `UPDATE_OPENAPI=1 cargo test openapi` \
+             and check the difference is intended"
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    Extension, Json,
+    extract::{Path, Query},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{AuthMethod, Principal, Scope},
+    location::{self, Coordinates},
+    store,
+    v1::{ApiError, Forecast, ForecastStep},
+    weather,
+};
+
+const PLACES_NAMESPACE: &str = "places";
+// Whose places anonymous requests read. Other owners are prefixed with their
+// kind, so no user or token name can stand for this one.
+const SHARED_OWNER: &str = "shared";
+const MAX_PLACES: usize = 50;
+const MAX_NAME_LENGTH: usize = 100;
+
+// Changes read, modify and write a whole list, so they take turns
+static WRITE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+type PlacesError = (StatusCode, String);
+
+/// A saved location, like home or the cabin.
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
+pub struct Place {
+    pub id: String,
+    /// The place name, e.g. "Oslo"
+    pub name: String,
+    /// What the place is to its owner, e.g. "Home" or "Cabin"
+    pub label: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// IANA timezone, e.g. Europe/Oslo
+    pub timezone: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Place {
+    fn coordinates(&self) -> Coordinates {
+        Coordinates::new(self.latitude, self.longitude)
+    }
+}
+
+/// A new place. Without coordinates the name is geocoded.
+#[derive(Deserialize, ToSchema)]
+pub struct CreatePlace {
+    name: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+/// Changes to a place; fields left out stay as they are, and `null` clears
+/// the label or timezone.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdatePlace {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default, with = "double_option")]
+    #[schema(value_type = Option<String>)]
+    label: Option<Option<String>>,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+    #[serde(default, with = "double_option")]
+    #[schema(value_type = Option<String>)]
+    timezone: Option<Option<String>>,
+}
+
+// Tells a field that was left out (None) from one set to null (Some(None))
+mod double_option {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Option::deserialize(deserializer).map(Some)
+    }
+}
+
+/// The new order of all places, by id.
+#[derive(Deserialize, ToSchema)]
+pub struct PlaceOrder {
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OwnerQuery {
+    /// The shared places that requests without credentials see, rather than
+    /// your own. Changing them needs the admin scope.
+    #[serde(default)]
+    shared: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchQuery {
+    /// Comma separated place ids; all places, in order, if left out
+    #[serde(default)]
+    places: Option<String>,
+    /// As for the places themselves
+    #[serde(default)]
+    shared: bool,
+}
+
+/// The weather at one place, or why there isn't any.
+#[derive(Serialize, ToSchema)]
+pub struct PlaceWeather {
+    pub id: String,
+    /// Left out for ids that aren't saved places (any more)
+    pub place: Option<Place>,
+    pub current: Option<ForecastStep>,
+    pub error: Option<String>,
+}
+
+// Users and static tokens get lists of their own even when their names are
+// the same
+fn owner(principal: Option<Extension<Principal>>, shared: bool) -> String {
+    match principal {
+        Some(Extension(principal)) if !shared => match principal.method {
+            AuthMethod::StaticToken => format!("token:{}", principal.name),
+            AuthMethod::ApiToken | AuthMethod::Session => format!("user:{}", principal.name),
+        },
+        _ => SHARED_OWNER.to_string(),
+    }
+}
+
+// The write scope is enough for your own places; the shared ones are what
+// every visitor sees, so they take the admin scope
+fn writable_owner(
+    principal: Option<Extension<Principal>>,
+    shared: bool,
+) -> Result<String, PlacesError> {
+    let is_admin = principal
+        .as_ref()
+        .is_some_and(|Extension(principal)| principal.allows(Scope::Admin));
+    if shared && !is_admin {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Changing the shared places needs the admin scope".to_string(),
+        ));
+    }
+    Ok(owner(principal, shared))
+}
+
+fn internal_error(e: impl std::fmt::Display) -> PlacesError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn invalid(message: impl Into<String>) -> PlacesError {
+    (StatusCode::UNPROCESSABLE_ENTITY, message.into())
+}
+
+fn not_found(id: &str) -> PlacesError {
+    (StatusCode::NOT_FOUND, format!("No place {}", id))
+}
+
+fn new_id() -> String {
+    let mut bytes = [0u8; 6];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+async fn load(owner: &str) -> Result<Vec<Place>, PlacesError> {
+    store::get(PLACES_NAMESPACE, owner)
+        .await
+        .map(Option::unwrap_or_default)
+        .map_err(internal_error)
+}
+
+async fn save(owner: &str, places: &[Place]) -> Result<(), PlacesError> {
+    store::upsert(PLACES_NAMESPACE, owner, &places, None, None)
+        .await
+        .map_err(internal_error)
+}
+
+fn validate_name(name: &str) -> Result<String, PlacesError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(invalid(format!(
+            "Names must be 1 to {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_label(label: Option<String>) -> Result<Option<String>, PlacesError> {
+    match label.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(label) => validate_name(label).map(Some),
+    }
+}
+
+fn validate_timezone(timezone: Option<String>) -> Result<Option<String>, PlacesError> {
+    match timezone {
+        Some(timezone) => match timezone.trim().parse::<Tz>() {
+            Ok(tz) => Ok(Some(tz.name().to_string())),
+            Err(e) => Err(invalid(format!("Invalid timezone: {}", e))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), PlacesError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(invalid("Latitude must be within ±90 and longitude ±180"));
+    }
+    Ok(())
+}
+
+/// Places of whoever is signed in, in their order.
+#[utoipa::path(
+    get,
+    operation_id = "listPlaces",
+    path = "/api/v1/places",
+    tag = "places",
+    params(OwnerQuery),
+    responses(
+        (status = 200, body = Vec<Place>),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_list_places(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<OwnerQuery>,
+) -> Result<Json<Vec<Place>>, PlacesError> {
+    Ok(Json(load(&owner(principal, query.shared)).await?))
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "getPlace",
+    path = "/api/v1/places/{id}",
+    tag = "places",
+    params(("id" = String, Path), OwnerQuery),
+    responses(
+        (status = 200, body = Place),
+        (status = 404, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_get_place(
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+    Query(query): Query<OwnerQuery>,
+) -> Result<Json<Place>, PlacesError> {
+    let places = load(&owner(principal, query.shared)).await?;
+    match places.into_iter().find(|place| place.id == id) {
+        Some(place) => Ok(Json(place)),
+        None => Err(not_found(&id)),
+    }
+}
+
+/// Adds a place at the end of the list. Needs the write scope.
+#[utoipa::path(
+    post,
+    operation_id = "createPlace",
+    path = "/api/v1/places",
+    tag = "places",
+    params(OwnerQuery),
+    request_body = CreatePlace,
+    responses(
+        (status = 201, body = Place),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 422, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_create_place(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<OwnerQuery>,
+    Json(request): Json<CreatePlace>,
+) -> Result<(StatusCode, Json<Place>), PlacesError> {
+    let owner = writable_owner(principal, query.shared)?;
+    let name = validate_name(&request.name)?;
+    let label = validate_label(request.label)?;
+    let timezone = validate_timezone(request.timezone)?;
+    let (latitude, longitude) = match (request.latitude, request.longitude) {
+        (Some(latitude), Some(longitude)) => (latitude, longitude),
+        (None, None) => {
+            let coords = location::get_coordinates(&name)
+                .await
+                .map_err(|e| invalid(format!("Couldn't find {}: {}", name, e)))?;
+            (coords.lat(), coords.lon())
+        }
+        _ => return Err(invalid("Give both latitude and longitude, or neither")),
+    };
+    validate_coordinates(latitude, longitude)?;
+
+    let _guard = WRITE_LOCK.lock().await;
+    let mut places = load(&owner).await?;
+    if places.len() >= MAX_PLACES {
+        return Err(invalid(format!("At most {} places", MAX_PLACES)));
+    }
+    let place = Place {
+        id: new_id(),
+        name,
+        label,
+        latitude,
+        longitude,
+        timezone,
+        created_at: Utc::now(),
+    };
+    places.push(place.clone());
+    save(&owner, &places).await?;
+    Ok((StatusCode::CREATED, Json(place)))
+}
+
+/// Renames, relabels or moves a place. Needs the write scope.
+#[utoipa::path(
+    patch,
+    operation_id = "updatePlace",
+    path = "/api/v1/places/{id}",
+    tag = "places",
+    params(("id" = String, Path), OwnerQuery),
+    request_body = UpdatePlace,
+    responses(
+        (status = 200, body = Place),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 404, response = ApiError),
+        (status = 422, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_update_place(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<OwnerQuery>,
+    Path(id): Path<String>,
+    Json(request): Json<UpdatePlace>,
+) -> Result<Json<Place>, PlacesError> {
+    let owner = writable_owner(principal, query.shared)?;
+    let _guard = WRITE_LOCK.lock().await;
+    let mut places = load(&owner).await?;
+    let Some(place) = places.iter_mut().find(|place| place.id == id) else {
+        return Err(not_found(&id));
+    };
+
+    if let Some(name) = request.name {
+        place.name = validate_name(&name)?;
+    }
+    if let Some(label) = request.label {
+        place.label = validate_label(label)?;
+    }
+    if let Some(timezone) = request.timezone {
+        place.timezone = validate_timezone(timezone)?;
+    }
+    let latitude = request.latitude.unwrap_or(place.latitude);
+    let longitude = request.longitude.unwrap_or(place.longitude);
+    validate_coordinates(latitude, longitude)?;
+    place.latitude = latitude;
+    place.longitude = longitude;
+
+    let place = place.clone();
+    save(&owner, &places).await?;
+    Ok(Json(place))
+}
+
+/// Removes a place. Needs the write scope.
+#[utoipa::path(
+    delete,
+    operation_id = "deletePlace",
+    path = "/api/v1/places/{id}",
+    tag = "places",
+    params(("id" = String, Path), OwnerQuery),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 404, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_delete_place(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<OwnerQuery>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, PlacesError> {
+    let owner = writable_owner(principal, query.shared)?;
+    let _guard = WRITE_LOCK.lock().await;
+    let mut places = load(&owner).await?;
+    let count = places.len();
+    places.retain(|place| place.id != id);
+    if places.len() == count {
+        return Err(not_found(&id));
+    }
+    save(&owner, &places).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Puts the places in a new order. Every place must be listed once. Needs
+/// the write scope.
+#[utoipa::path(
+    put,
+    operation_id = "reorderPlaces",
+    path = "/api/v1/places/order",
+    tag = "places",
+    params(OwnerQuery),
+    request_body = PlaceOrder,
+    responses(
+        (status = 200, body = Vec<Place>),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 422, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_reorder_places(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<OwnerQuery>,
+    Json(order): Json<PlaceOrder>,
+) -> Result<Json<Vec<Place>>, PlacesError> {
+    let owner = writable_owner(principal, query.shared)?;
+    let _guard = WRITE_LOCK.lock().await;
+    let mut places = load(&owner).await?;
+    let mut reordered = Vec::with_capacity(places.len());
+    for id in &order.ids {
+        match places.iter().position(|place| place.id == *id) {
+            Some(index) => reordered.push(places.swap_remove(index)),
+            None => return Err(invalid(format!("Unknown or repeated place {}", id))),
+        }
+    }
+    if !places.is_empty() {
+        return Err(invalid("Every place must be in the new order"));
+    }
+    save(&owner, &reordered).await?;
+    Ok(Json(reordered))
+}
+
+async fn place_weather(place: Place) -> PlaceWeather {
+    match weather::fetch_weather(&place.coordinates()).await {
+        Ok(forecast) => PlaceWeather {
+            id: place.id.clone(),
+            current: Forecast::from_met(place.name.clone(), forecast)
+                .steps
+                .into_iter()
+                .next(),
+            error: None,
+            place: Some(place),
+        },
+        Err(e) => {
+            tracing::warn!(place = %place.id, "Failed to fetch weather for place: {}", e);
+            PlaceWeather {
+                id: place.id.clone(),
+                place: Some(place),
+                current: None,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Current conditions at several places in one go. A place whose weather
+/// can't be had, or an id that isn't a saved place, comes back with an
+/// `error` instead of failing the batch.
+#[utoipa::path(
+    get,
+    operation_id = "getPlacesWeather",
+    path = "/api/v1/weather/batch",
+    tag = "places",
+    params(BatchQuery),
+    responses(
+        (status = 200, body = Vec<PlaceWeather>),
+        (status = 500, response = ApiError)
+    )
+)]
+pub async fn handle_batch_weather(
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<BatchQuery>,
+) -> Result<Json<Vec<PlaceWeather>>, PlacesError> {
+    let places = load(&owner(principal, query.shared)).await?;
+    let ids: Vec<&str> = match query.places.as_deref().map(str::trim) {
+        None | Some("") => places.iter().map(|place| place.id.as_str()).collect(),
+        Some(ids) => ids.split(',').map(str::trim).collect(),
+    };
+    // A dashboard can outlive places it lists; those get an error of their own
+    let results = ids.into_iter().map(|id| {
+        let place = places.iter().find(|place| place.id == id).cloned();
+        async move {
+            match place {
+                Some(place) => place_weather(place).await,
+                None => PlaceWeather {
+                    id: id.to_string(),
+                    place: None,
+                    current: None,
+                    error: Some(not_found(id).1),
+                },
+            }
+        }
+    });
+    Ok(Json(join_all(results).await))
+}
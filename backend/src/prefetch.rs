@@ -0,0 +1,164 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+use crate::{
+    alerts, diagnostics,
+    events::{self, EventKind},
+    location, shutdown,
+    tracking::{self, TrackedLocation},
+    weather,
+};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+/// Forecasts of active locations are refreshed this long before they expire.
+const REFRESH_MARGIN_SECONDS: i64 = 60;
+/// Seconds since the last view, and how often a location that idle is refreshed.
+/// Only locations in the first tier get their summaries pre-generated, and
+/// only when met.no has a new forecast run or the alerts have changed.
+const BACKOFF: [(i64, i64); 3] = [
+    (60 * 60, weather::CACHE_SECONDS),
+    (24 * 60 * 60, 60 * 60),
+    (i64::MAX, 6 * 60 * 60),
+];
+
+// Configured locations are on a dashboard somewhere, so they're always active
+fn refresh_tier(view: Option<&TrackedLocation>, now: DateTime<Utc>) -> usize {
+    let idle = view.map_or(0, |v| {
+        now.signed_duration_since(v.last_viewed).num_seconds()
+    });
+    BACKOFF
+        .iter()
+        .position(|&(idle_limit, _)| idle < idle_limit)
+        .unwrap_or(BACKOFF.len() - 1)
+}
+
+#[tracing::instrument(skip_all, fields(location = %location))]
+async fn prefetch_location(
+    location: &str,
+    view: Option<&TrackedLocation>,
+    last_refresh: &mut HashMap<String, DateTime<Utc>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let now = Utc::now();
+    let tier = refresh_tier(view, now);
+    let interval = BACKOFF[tier].1;
+    let due = |refreshed_at: Option<DateTime<Utc>>| {
+        refreshed_at.is_none_or(|t| {
+            now.signed_duration_since(t).num_seconds() >= interval - REFRESH_MARGIN_SECONDS
+        })
+    };
+    if !due(last_refresh.get(location).copied()) {
+        return Ok(());
+    }
+
+    // A name that doesn't geocode waits for its next refresh like any other,
+    // rather than going back to Nominatim every tick
+    let coords = match location::get_coordinates(location).await {
+        Ok(coords) => coords,
+        Err(e) => {
+            last_refresh.insert(location.to_string(), now);
+            return Err(e);
+        }
+    };
+    // A forecast fetched for a visitor counts as a refresh too
+    let refreshed_at = weather::weather_cached_at(&coords)
+        .await
+        .into_iter()
+        .chain(last_refresh.get(location).copied())
+        .max();
+    if !due(refreshed_at) {
+        return Ok(());
+    }
+
+    last_refresh.insert(location.to_string(), now);
+    let weather_data = weather::refresh_weather(&coords).await?;
+
+    if tier == 0 {
+        let active_alerts = alerts::alerts_for(&coords).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to fetch weather alerts: {}", e);
+            Vec::new()
+        });
+        // Anything else would spend the daily LLM budget saying the same thing
+        // again; a viewer's request regenerates the summary once it expires
+        if std::env::var("GOOGLE_AISTUDIO_API_KEY").is_ok()
+            && weather::summary_outdated(&weather_data, &active_alerts).await
+        {
+            let timezone = view.and_then(|v| v.timezone.clone());
+            let summary = weather::refresh_summary(&weather_data, &active_alerts, timezone).await?;
+            events::publish(&coords, EventKind::Summary, &json!({ "summary": summary }));
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure a location has current data to push to a new stream subscriber,
+/// from the caches where possible.
+pub async fn warm(
+    location: &str,
+    timezone: Option<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let coords = location::get_coordinates(location).await?;
+    let weather_data = weather::fetch_weather(&coords).await?;
+    events::publish(&coords, EventKind::Weather, &weather_data);
+
+    let active_alerts = alerts::alerts_for(&coords).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to fetch weather alerts: {}", e);
+        Vec::new()
+    });
+    if std::env::var("GOOGLE_AISTUDIO_API_KEY").is_ok() {
+        let summary = weather::summarize_weather(&weather_data, &active_alerts, timezone).await?;
+        events::publish(&coords, EventKind::Summary, &json!({ "summary": summary }));
+    }
+    Ok(())
+}
+
+/// Refetches everything about a location, ignoring the caches.
+pub async fn refresh(
+    location: &str,
+    timezone: Option<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let coords = location::get_coordinates(location).await?;
+    let weather_data = weather::refresh_weather(&coords).await?;
+
+    let active_alerts = alerts::alerts_for(&coords).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to fetch weather alerts: {}", e);
+        Vec::new()
+    });
+    if std::env::var("GOOGLE_AISTUDIO_API_KEY").is_ok() {
+        let summary = weather::refresh_summary(&weather_data, &active_alerts, timezone).await?;
+        events::publish(&coords, EventKind::Summary, &json!({ "summary": summary }));
+    }
+    Ok(())
+}
+
+/// Keeps the forecasts and summaries of tracked locations warm, so dashboard
+/// loads don't have to wait for met.no or the model. Stops at shutdown.
+pub fn spawn_scheduler() -> JoinHandle<()> {
+    tokio::spawn(async {
+        let mut last_refresh: HashMap<String, DateTime<Utc>> = HashMap::new();
+        let mut interval = tokio::time::interval(TICK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = shutdown::requested() => break,
+            }
+            diagnostics::task_started("prefetch");
+            let tracked = tracking::tracked_views().await;
+            last_refresh.retain(|location, _| tracked.iter().any(|(l, _)| l == location));
+            let mut failures = 0;
+            for (location, view) in &tracked {
+                if shutdown::is_shutting_down() {
+                    break;
+                }
+                if let Err(e) = prefetch_location(location, view.as_ref(), &mut last_refresh).await
+                {
+                    tracing::warn!("Failed to prefetch weather for {}: {}", location, e);
+                    failures += 1;
+                }
+            }
+            diagnostics::task_finished("prefetch", tracked.len(), failures);
+        }
+    })
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::Principal, store};
+
+const USAGE_NAMESPACE: &str = "usage";
+/// Hosts whose usage policies ask for a minimum spacing between requests.
+const MIN_INTERVALS: &[(&str, Duration)] =
+    &[("nominatim.openstreetmap.org", Duration::from_secs(1))];
+/// Hosts with an hourly request budget: the variable overriding it, and the default.
+const HOURLY_BUDGETS: &[(&str, &str, u64)] = &[("api.met.no", "METNO_HOURLY_BUDGET", 1000)];
+const LLM_DAILY_CALLS: u64 = 200;
+// Buckets of clients that went quiet are dropped once there are this many
+const MAX_BUCKETS: usize = 10_000;
+
+static BUCKETS: Lazy<Mutex<HashMap<(&'static str, String), Bucket>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_SLOTS: Lazy<tokio::sync::Mutex<HashMap<&'static str, Instant>>> =
+    Lazy::new(|| tokio::sync::Mutex::new(HashMap::new()));
+static USAGE: Lazy<tokio::sync::Mutex<Option<HashMap<String, Usage>>>> =
+    Lazy::new(|| tokio::sync::Mutex::new(None));
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A per-client limit: requests per minute, with bursts up to that many.
+#[derive(Clone, Copy)]
+struct ClientLimit {
+    name: &'static str,
+    variable: &'static str,
+    default_per_minute: u32,
+}
+
+const API_LIMIT: ClientLimit = ClientLimit {
+    name: "api",
+    variable: "RATE_LIMIT_PER_MINUTE",
+    default_per_minute: 120,
+};
+const SUMMARY_LIMIT: ClientLimit = ClientLimit {
+    name: "summary",
+    variable: "SUMMARY_RATE_LIMIT_PER_MINUTE",
+    default_per_minute: 6,
+};
+// Each attempt is an Argon2 hash, and each is a password guess
+const LOGIN_LIMIT: ClientLimit = ClientLimit {
+    name: "login",
+    variable: "LOGIN_RATE_LIMIT_PER_MINUTE",
+    default_per_minute: 5,
+};
+
+impl ClientLimit {
+    fn per_minute(self) -> u32 {
+        std::env::var(self.variable)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(self.default_per_minute)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct Usage {
+    requests: u64,
+    #[serde(default)]
+    tokens: u64,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// How much of a budget is used, for the diagnostics endpoint.
+#[derive(Serialize, Debug)]
+pub struct BudgetStatus {
+    pub budget: String,
+    pub period: String,
+    pub requests: u64,
+    pub request_limit: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_limit: Option<u64>,
+}
+
+// How many proxies in front of the backend append to X-Forwarded-For:
+// TRUST_FORWARDED_FOR is a count, or "true" for one. None by default.
+fn trusted_proxy_hops() -> usize {
+    match std::env::var("TRUST_FORWARDED_FOR")
+        .as_deref()
+        .map(str::trim)
+    {
+        Ok("true") => 1,
+        Ok(hops) => hops.parse().unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+// The address the outermost trusted proxy saw. Clients can send anything in
+// X-Forwarded-For, so only entries the proxies appended on the right count.
+fn forwarded_for(value: &str, hops: usize) -> Option<String> {
+    let entries: Vec<&str> = value.split(',').map(str::trim).collect();
+    entries
+        .get(entries.len().saturating_sub(hops))
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.to_string())
+}
+
+// Whoever is calling: the authenticated principal, else the client address.
+// Behind a tunnel or proxy every request comes from the same address, so
+// X-Forwarded-For is used when TRUST_FORWARDED_FOR is set.
+fn client_id(request: &Request) -> String {
+    if let Some(principal) = request.extensions().get::<Principal>() {
+        return format!("principal:{}", principal.name);
+    }
+    let hops = trusted_proxy_hops();
+    let forwarded = (hops > 0)
+        .then(|| {
+            request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| forwarded_for(value, hops))
+        })
+        .flatten();
+    forwarded
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Takes a token from the client's bucket, or says how long until one is back
+fn take_token(limit: ClientLimit, client: String) -> Result<(), Duration> {
+    let per_minute = limit.per_minute();
+    if per_minute == 0 {
+        return Ok(());
+    }
+    let capacity = per_minute as f64;
+    let refill_per_second = capacity / 60.0;
+    let now = Instant::now();
+
+    let mut buckets = BUCKETS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if buckets.len() >= MAX_BUCKETS {
+        buckets.retain(|_, bucket| now.duration_since(bucket.updated) < Duration::from_secs(60));
+    }
+    let bucket = buckets.entry((limit.name, client)).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / refill_per_second,
+        ))
+    }
+}
+
+async fn limit(limit: ClientLimit, request: Request, next: Next) -> Response {
+    match take_token(limit, client_id(&request)) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs().max(1).to_string(),
+            )],
+            "Too many requests",
+        )
+            .into_response(),
+    }
+}
+
+/// Limits each client to `RATE_LIMIT_PER_MINUTE` API requests (default 120).
+pub async fn limit_api(request: Request, next: Next) -> Response {
+    limit(API_LIMIT, request, next).await
+}
+
+/// Limits each client to `SUMMARY_RATE_LIMIT_PER_MINUTE` summary requests
+/// (default 6), on top of the general limit.
+pub async fn limit_summary(request: Request, next: Next) -> Response {
+    limit(SUMMARY_LIMIT, request, next).await
+}
+
+/// Limits each client to `LOGIN_RATE_LIMIT_PER_MINUTE` login attempts
+/// (default 5), by address as nobody is signed in yet.
+pub async fn limit_login(request: Request, next: Next) -> Response {
+    limit(LOGIN_LIMIT, request, next).await
+}
+
+async fn load_usage() -> HashMap<String, Usage> {
+    match store::load_namespace(USAGE_NAMESPACE).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            tracing::warn!("Failed to load usage counters: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+// Adds to a counter unless that would take it past `limit`, and persists it.
+// Returns whether it was added.
+async fn spend(
+    key: String,
+    requests: u64,
+    tokens: u64,
+    limit: Option<u64>,
+    expires_at: DateTime<Utc>,
+) -> bool {
+    let mut usage = USAGE.lock().await;
+    if usage.is_none() {
+        *usage = Some(load_usage().await);
+    }
+    let Some(usage) = usage.as_mut() else {
+        return true;
+    };
+    usage.retain(|_, entry| entry.expires_at.is_none_or(|t| t > Utc::now()));
+
+    let entry = usage.entry(key.clone()).or_insert(Usage {
+        expires_at: Some(expires_at),
+        ..Default::default()
+    });
+    if limit.is_some_and(|limit| entry.requests + requests > limit) {
+        return false;
+    }
+    entry.requests += requests;
+    entry.tokens += tokens;
+    if let Err(e) = store::upsert(USAGE_NAMESPACE, &key, &*entry, Some(expires_at), None).await {
+        tracing::warn!("Failed to save usage counter: {}", e);
+    }
+    true
+}
+
+async fn current_usage(key: &str) -> Usage {
+    let mut usage = USAGE.lock().await;
+    if usage.is_none() {
+        *usage = Some(load_usage().await);
+    }
+    usage
+        .as_ref()
+        .and_then(|usage| usage.get(key))
+        .filter(|entry| entry.expires_at.is_none_or(|t| t > Utc::now()))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn hour_key(host: &str, now: DateTime<Utc>) -> String {
+    format!("{}:{}", host, now.format("%Y-%m-%dT%H"))
+}
+
+fn llm_key(now: DateTime<Utc>) -> String {
+    format!("llm:{}", now.format("%Y-%m-%d"))
+}
+
+fn hourly_budget(host: &str) -> Option<(String, u64)> {
+    HOURLY_BUDGETS
+        .iter()
+        .find(|(budget_host, _, _)| *budget_host == host)
+        .map(|(_, variable, default)| {
+            let limit = std::env::var(variable)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(*default);
+            (variable.to_string(), limit)
+        })
+}
+
+/// Waits for the host's next free slot if it asks for spaced out requests,
+/// and fails once its hourly budget is used up.
+pub async fn throttle_upstream(host: &str) -> Result<(), String> {
+    if let Some(&(host, interval)) = MIN_INTERVALS.iter().find(|(h, _)| *h == host) {
+        // Held while sleeping, so callers queue up one interval apart
+        let mut next_slots = NEXT_SLOTS.lock().await;
+        let now = Instant::now();
+        if let Some(&next) = next_slots.get(host)
+            && next > now
+        {
+            tokio::time::sleep(next - now).await;
+        }
+        next_slots.insert(host, Instant::now() + interval);
+    }
+
+    if let Some((variable, limit)) = hourly_budget(host) {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::hours(2);
+        if !spend(hour_key(host, now), 1, 0, Some(limit), expires_at).await {
+            return Err(format!(
+                "Hourly budget of {} requests to {} is used up (see {})",
+                limit, host, variable
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn llm_limits() -> (u64, Option<u64>) {
+    let calls = std::env::var("LLM_DAILY_CALL_LIMIT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(LLM_DAILY_CALLS);
+    let tokens = std::env::var("LLM_DAILY_TOKEN_LIMIT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok());
+    (calls, tokens)
+}
+
+/// Counts a model call against today's budget, or returns false if the
+/// `LLM_DAILY_CALL_LIMIT` calls or `LLM_DAILY_TOKEN_LIMIT` tokens are spent.
+pub async fn spend_llm_call() -> bool {
+    let now = Utc::now();
+    let (call_limit, token_limit) = llm_limits();
+    if let Some(token_limit) = token_limit
+        && current_usage(&llm_key(now)).await.tokens >= token_limit
+    {
+        return false;
+    }
+    let spent = spend(
+        llm_key(now),
+        1,
+        0,
+        Some(call_limit),
+        now + chrono::Duration::days(2),
+    )
+    .await;
+    if !spent {
+        tracing::debug!("Daily LLM budget is used up");
+    }
+    spent
+}
+
+/// Adds the tokens a model call used to today's budget.
+pub async fn record_llm_tokens(tokens: u64) {
+    let now = Utc::now();
+    spend(
+        llm_key(now),
+        0,
+        tokens,
+        None,
+        now + chrono::Duration::days(2),
+    )
+    .await;
+}
+
+/// Today's LLM usage and this hour's upstream usage against their budgets.
+pub async fn budgets() -> Vec<BudgetStatus> {
+    let now = Utc::now();
+    let (call_limit, token_limit) = llm_limits();
+    let llm = current_usage(&llm_key(now)).await;
+    let mut budgets = vec![BudgetStatus {
+        budget: "llm".to_string(),
+        period: now.format("%Y-%m-%d").to_string(),
+        requests: llm.requests,
+        request_limit: call_limit,
+        tokens: Some(llm.tokens),
+        token_limit,
+    }];
+    for (host, _, _) in HOURLY_BUDGETS {
+        let Some((_, limit)) = hourly_budget(host) else {
+            continue;
+        };
+        budgets.push(BudgetStatus {
+            budget: host.to_string(),
+            period: now.format("%Y-%m-%dT%H").to_string(),
+            requests: current_usage(&hour_key(host, now)).await.requests,
+            request_limit: limit,
+            tokens: None,
+            token_limit: None,
+        });
+    }
+    budgets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_for_ignores_entries_the_client_sent() {
+        let header = "203.0.113.9, 198.51.100.7, 10.0.0.2";
+        assert_eq!(forwarded_for(header, 1).as_deref(), Some("10.0.0.2"));
+        assert_eq!(forwarded_for(header, 2).as_deref(), Some("198.51.100.7"));
+        // Fewer entries than proxies: the outermost one is all there is
+        assert_eq!(forwarded_for("10.0.0.2", 2).as_deref(), Some("10.0.0.2"));
+        assert_eq!(forwarded_for("", 1), None);
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+// Docker waits 10 seconds after SIGTERM before it kills the process, so
+// leave some of that for flushing
+const DEFAULT_TIMEOUT_SECONDS: u64 = 8;
+
+static SHUTDOWN: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+/// How long in-flight requests and background tasks get to finish once
+/// shutdown starts: `SHUTDOWN_TIMEOUT_SECONDS` (default 8).
+pub fn timeout() -> Duration {
+    let seconds = std::env::var("SHUTDOWN_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+    Duration::from_secs(seconds)
+}
+
+pub fn is_shutting_down() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Resolves once shutdown starts, right away if it already has. Long-running
+/// loops select on this to wind down.
+pub async fn requested() {
+    let mut receiver = SHUTDOWN.subscribe();
+    // Only fails if the sender is dropped, and it's a static
+    let _ = receiver.wait_for(|shutting_down| *shutting_down).await;
+}
+
+/// Starts shutting down, if that hasn't happened yet.
+pub fn begin() {
+    SHUTDOWN.send_if_modified(|shutting_down| !std::mem::replace(shutting_down, true));
+}
+
+/// Waits for SIGINT or SIGTERM (what `docker stop` sends), then starts
+/// shutting down.
+pub async fn wait_for_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    let signal = tokio::select! {
+        _ = interrupt => "SIGINT",
+        _ = terminate => "SIGTERM",
+    };
+    tracing::info!(
+        "Received {}; shutting down within {}s",
+        signal,
+        timeout().as_secs()
+    );
+    begin();
+}
@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::task::JoinHandle;
+
+use crate::{diagnostics, shutdown};
+
+pub const DEFAULT_CACHE_DIR: &str = "cache";
+const DATABASE_FILE: &str = "dashboard.db";
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Days of weather history and forecast verification kept; a year covers the
+/// longest accuracy report.
+const DEFAULT_HISTORY_RETENTION_DAYS: i64 = 365;
+
+static DATABASE: OnceCell<Mutex<Connection>> = OnceCell::new();
+static CACHE_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+type StoreResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Each entry is applied once, in order, and recorded in `PRAGMA user_version`.
+// Never edit a migration that has shipped; add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: key/value cache shared by all caches, one namespace per cache
+    "CREATE TABLE cache_entries (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        stored_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        PRIMARY KEY (namespace, key)
+    );
+    CREATE INDEX cache_entries_expires_at ON cache_entries (expires_at);
+    CREATE INDEX cache_entries_stored_at ON cache_entries (namespace, stored_at);",
+    // 2: recorded "now" conditions per location
+    "CREATE TABLE weather_history (
+        coord_key TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL,
+        air_temperature REAL,
+        precipitation_amount REAL,
+        wind_speed REAL,
+        wind_speed_of_gust REAL,
+        wind_from_direction REAL,
+        air_pressure_at_sea_level REAL,
+        relative_humidity REAL,
+        cloud_area_fraction REAL,
+        PRIMARY KEY (coord_key, recorded_at)
+    );",
+    // 3: forecast verification
+    "CREATE TABLE forecast_predictions (
+        coord_key TEXT NOT NULL,
+        provider TEXT NOT NULL,
+        target_time INTEGER NOT NULL,
+        lead_hours INTEGER NOT NULL,
+        issued_at INTEGER NOT NULL,
+        air_temperature REAL,
+        precipitation_amount REAL,
+        PRIMARY KEY (coord_key, provider, target_time, lead_hours)
+    );
+    CREATE INDEX forecast_predictions_target ON forecast_predictions (coord_key, target_time);
+    CREATE TABLE observations (
+        coord_key TEXT NOT NULL,
+        observed_at INTEGER NOT NULL,
+        air_temperature REAL,
+        precipitation_amount REAL,
+        PRIMARY KEY (coord_key, observed_at)
+    );",
+];
+
+/// Moves everything the store keeps on disk out of `cache/`. Only takes
+/// effect before the database is first opened.
+pub fn set_cache_dir(dir: PathBuf) {
+    if CACHE_DIR.set(dir).is_err() {
+        tracing::warn!("Cache directory already set; keeping the first one");
+    }
+}
+
+pub fn cache_dir() -> &'static Path {
+    CACHE_DIR.get_or_init(|| PathBuf::from(DEFAULT_CACHE_DIR))
+}
+
+fn database_file() -> PathBuf {
+    cache_dir().join(DATABASE_FILE)
+}
+
+fn open() -> StoreResult<Connection> {
+    std::fs::create_dir_all(cache_dir())?;
+    let mut conn = Connection::open(database_file())?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    // auto_vacuum only takes effect on a fresh database, before any table exists
+    conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection) -> StoreResult<()> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index as i64 + 1)?;
+        tx.commit()?;
+        tracing::info!("Applied database migration {}", index + 1);
+    }
+    Ok(())
+}
+
+/// Runs `f` against the database on a blocking thread.
+pub async fn with_db<T, F>(f: F) -> StoreResult<T>
+where
+    F: FnOnce(&mut Connection) -> StoreResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let db = DATABASE.get_or_try_init(|| open().map(Mutex::new))?;
+        let mut conn = db.lock().map_err(|_| "Database lock poisoned")?;
+        f(&mut conn)
+    })
+    .await?
+}
+
+/// All unexpired entries of a cache namespace. Entries that no longer
+/// deserialize (e.g. after a struct change) are skipped.
+pub async fn load_namespace<T>(namespace: &'static str) -> StoreResult<HashMap<String, T>>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT key, value FROM cache_entries
+             WHERE namespace = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+        )?;
+        let rows = stmt.query_map(params![namespace, Utc::now().timestamp()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut entries = HashMap::new();
+        for row in rows {
+            let (key, value) = row?;
+            match serde_json::from_str(&value) {
+                Ok(parsed) => {
+                    entries.insert(key, parsed);
+                }
+                Err(e) => tracing::warn!(
+                    "Skipping unreadable {} cache entry {}: {}",
+                    namespace,
+                    key,
+                    e
+                ),
+            }
+        }
+        Ok(entries)
+    })
+    .await
+}
+
+/// A single unexpired entry, if there is one that parses.
+pub async fn get<T>(namespace: &'static str, key: &str) -> StoreResult<Option<T>>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let key = key.to_string();
+    with_db(move |conn| {
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM cache_entries
+                 WHERE namespace = ?1 AND key = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+                params![namespace, key, Utc::now().timestamp()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?)
+    })
+    .await
+}
+
+/// Inserts or replaces a cache entry. Expired entries in the same namespace are
+/// dropped in the same transaction, and when `max_entries` is given the oldest
+/// entries beyond it are too.
+pub async fn upsert<T: Serialize>(
+    namespace: &'static str,
+    key: &str,
+    value: &T,
+    expires_at: Option<DateTime<Utc>>,
+    max_entries: Option<usize>,
+) -> StoreResult<()> {
+    let key = key.to_string();
+    let value = serde_json::to_string(value)?;
+    let expires_at = expires_at.map(|t| t.timestamp());
+
+    with_db(move |conn| {
+        let now = Utc::now().timestamp();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO cache_entries (namespace, key, value, stored_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (namespace, key) DO UPDATE SET
+                value = excluded.value,
+                stored_at = excluded.stored_at,
+                expires_at = excluded.expires_at",
+            params![namespace, key, value, now, expires_at],
+        )?;
+        tx.execute(
+            "DELETE FROM cache_entries WHERE namespace = ?1 AND expires_at <= ?2",
+            params![namespace, now],
+        )?;
+        if let Some(max_entries) = max_entries {
+            tx.execute(
+                "DELETE FROM cache_entries WHERE namespace = ?1 AND key NOT IN (
+                    SELECT key FROM cache_entries WHERE namespace = ?1
+                    ORDER BY stored_at DESC LIMIT ?2
+                )",
+                params![namespace, max_entries as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Serialize, Debug)]
+pub struct StoreStats {
+    pub file_size_bytes: u64,
+    /// Unexpired entries per cache namespace
+    pub cache_entries: BTreeMap<String, i64>,
+    /// Rows per table
+    pub tables: BTreeMap<String, i64>,
+}
+
+pub async fn stats() -> StoreResult<StoreStats> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT namespace, COUNT(*) FROM cache_entries
+             WHERE expires_at IS NULL OR expires_at > ?1
+             GROUP BY namespace",
+        )?;
+        let cache_entries = stmt
+            .query_map(params![Utc::now().timestamp()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut tables = BTreeMap::new();
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        for name in names {
+            // Table names come from sqlite_master itself, not from a request
+            let count: i64 =
+                conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", name), [], |row| {
+                    row.get(0)
+                })?;
+            tables.insert(name, count);
+        }
+
+        Ok(StoreStats {
+            file_size_bytes: std::fs::metadata(database_file()).map_or(0, |m| m.len()),
+            cache_entries,
+            tables,
+        })
+    })
+    .await
+}
+
+/// Checks that the cache directory accepts new files.
+pub fn check_writable() -> StoreResult<()> {
+    let dir = cache_dir();
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-probe");
+    std::fs::write(&probe, b"ok")?;
+    std::fs::remove_file(probe)?;
+    Ok(())
+}
+
+/// Checks that the database opens and answers queries.
+pub async fn check_database() -> StoreResult<()> {
+    with_db(|conn| Ok(conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?))
+        .await
+        .map(|_| ())
+}
+
+/// Removes an entry; returns whether there was one.
+pub async fn delete(namespace: &'static str, key: &str) -> StoreResult<bool> {
+    let key = key.to_string();
+    with_db(move |conn| {
+        let removed = conn.execute(
+            "DELETE FROM cache_entries WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+        )?;
+        Ok(removed > 0)
+    })
+    .await
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryInfo {
+    pub key: String,
+    pub stored_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+fn entry_info(row: &rusqlite::Row, with_value: bool) -> rusqlite::Result<EntryInfo> {
+    let value = if with_value {
+        let raw: String = row.get(3)?;
+        Some(serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw)))
+    } else {
+        None
+    };
+    Ok(EntryInfo {
+        key: row.get(0)?,
+        stored_at: DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_default(),
+        expires_at: row
+            .get::<_, Option<i64>>(2)?
+            .and_then(|t| DateTime::from_timestamp(t, 0)),
+        value,
+    })
+}
+
+/// Keys and timestamps of the unexpired entries of a namespace, newest first.
+pub async fn entries(namespace: &'static str) -> StoreResult<Vec<EntryInfo>> {
+    with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT key, stored_at, expires_at FROM cache_entries
+             WHERE namespace = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+             ORDER BY stored_at DESC",
+        )?;
+        let rows = stmt.query_map(params![namespace, Utc::now().timestamp()], |row| {
+            entry_info(row, false)
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    })
+    .await
+}
+
+/// A single entry with its value, expired or not.
+pub async fn entry(namespace: &'static str, key: &str) -> StoreResult<Option<EntryInfo>> {
+    let key = key.to_string();
+    with_db(move |conn| {
+        let entry = conn
+            .query_row(
+                "SELECT key, stored_at, expires_at, value FROM cache_entries
+                 WHERE namespace = ?1 AND key = ?2",
+                params![namespace, key],
+                |row| entry_info(row, true),
+            )
+            .optional()?;
+        Ok(entry)
+    })
+    .await
+}
+
+/// A cache entry as written by `cache export`, value and all.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedEntry {
+    pub namespace: String,
+    pub key: String,
+    pub value: serde_json::Value,
+    pub stored_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// Every entry of the given namespaces, or of all of them, expired or not.
+pub async fn export(namespaces: Vec<String>) -> StoreResult<Vec<ExportedEntry>> {
+    with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT namespace, key, value, stored_at, expires_at FROM cache_entries
+             ORDER BY namespace, key",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let raw: String = row.get(2)?;
+            Ok(ExportedEntry {
+                namespace: row.get(0)?,
+                key: row.get(1)?,
+                value: serde_json::from_str(&raw).unwrap_or(serde_json::Value::String(raw)),
+                stored_at: row.get(3)?,
+                expires_at: row.get(4)?,
+            })
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let entry = row?;
+            if namespaces.is_empty() || namespaces.contains(&entry.namespace) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    })
+    .await
+}
+
+/// Writes exported entries back, replacing any with the same key, in one
+/// transaction.
+pub async fn import(entries: Vec<ExportedEntry>) -> StoreResult<usize> {
+    with_db(move |conn| {
+        let tx = conn.transaction()?;
+        for entry in &entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO cache_entries (namespace, key, value, stored_at, expires_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    entry.namespace,
+                    entry.key,
+                    serde_json::to_string(&entry.value)?,
+                    entry.stored_at,
+                    entry.expires_at
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(entries.len())
+    })
+    .await
+}
+
+/// Removes every entry of the given namespaces.
+pub async fn clear(namespaces: Vec<String>) -> StoreResult<usize> {
+    with_db(move |conn| {
+        let tx = conn.transaction()?;
+        let mut removed = 0;
+        for namespace in &namespaces {
+            removed += tx.execute(
+                "DELETE FROM cache_entries WHERE namespace = ?1",
+                params![namespace],
+            )?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    })
+    .await
+}
+
+// `HISTORY_RETENTION_DAYS`; 0 keeps everything
+fn history_retention_days() -> i64 {
+    std::env::var("HISTORY_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_RETENTION_DAYS)
+}
+
+/// Drops expired entries and history older than `HISTORY_RETENTION_DAYS`,
+/// and returns freed pages to the filesystem.
+pub async fn vacuum() -> StoreResult<usize> {
+    let retention_days = history_retention_days();
+    with_db(move |conn| {
+        let now = Utc::now();
+        let mut removed = conn.execute(
+            "DELETE FROM cache_entries WHERE expires_at <= ?1",
+            params![now.timestamp()],
+        )?;
+        if retention_days > 0 {
+            let cutoff = (now - chrono::Duration::days(retention_days)).timestamp();
+            removed += conn.execute(
+                "DELETE FROM weather_history WHERE recorded_at < ?1",
+                params![cutoff],
+            )?;
+            removed += conn.execute(
+                "DELETE FROM forecast_predictions WHERE target_time < ?1",
+                params![cutoff],
+            )?;
+            removed += conn.execute(
+                "DELETE FROM observations WHERE observed_at < ?1",
+                params![cutoff],
+            )?;
+        }
+        conn.execute_batch("PRAGMA incremental_vacuum; PRAGMA optimize;")?;
+        Ok(removed)
+    })
+    .await
+}
+
+/// Moves everything in the write-ahead log into the database file, so the
+/// file alone is complete, e.g. for copying it off a stopped container.
+/// Each write is already its own transaction, so there's nothing half done
+/// to flush.
+pub async fn checkpoint() -> StoreResult<()> {
+    if DATABASE.get().is_none() {
+        return Ok(());
+    }
+    with_db(|conn| {
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    })
+    .await
+}
+
+/// Periodically vacuums the database until shutdown.
+pub fn spawn_maintenance() -> JoinHandle<()> {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = shutdown::requested() => break,
+            }
+            diagnostics::task_started("maintenance");
+            match vacuum().await {
+                Ok(removed) => {
+                    if removed > 0 {
+                        tracing::info!(
+                            "Removed {} expired cache entries and history rows",
+                            removed
+                        );
+                    }
+                    diagnostics::task_finished("maintenance", removed, 0);
+                }
+                Err(e) => {
+                    tracing::warn!("Database maintenance failed: {}", e);
+                    diagnostics::task_finished("maintenance", 0, 1);
+                }
+            }
+        }
+    })
+}
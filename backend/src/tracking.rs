@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::store;
+
+const TRACKED_NAMESPACE: &str = "tracked_location";
+/// Locations nobody has looked at for this long stop being tracked.
+const TRACKING_DAYS: i64 = 7;
+// Views are frequent; only persist them this often per location
+const PERSIST_INTERVAL_SECONDS: i64 = 3600;
+// Anyone can ask for any place name, so the least recently viewed locations
+// are dropped beyond this many
+const MAX_TRACKED_LOCATIONS: usize = 500;
+
+static TRACKED: Lazy<RwLock<Option<TrackedLocations>>> = Lazy::new(|| RwLock::new(None));
+
+type TrackedLocations = HashMap<String, TrackedLocation>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TrackedLocation {
+    pub last_viewed: DateTime<Utc>,
+    /// Timezone of the latest summary request, used when pre-generating
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(skip)]
+    persisted_at: Option<DateTime<Utc>>,
+}
+
+/// Locations from `TRACKED_LOCATIONS`, which are tracked whether viewed or not.
+pub fn configured_locations() -> Vec<String> {
+    std::env::var("TRACKED_LOCATIONS")
+        .map(|locations| {
+            locations
+                .split(',')
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn load_tracked() -> TrackedLocations {
+    match store::load_namespace(TRACKED_NAMESPACE).await {
+        Ok(tracked) => tracked,
+        Err(e) => {
+            tracing::warn!("Failed to load tracked locations: {}", e);
+            TrackedLocations::new()
+        }
+    }
+}
+
+// Drops locations idle past the tracking window, then the least recently
+// viewed ones beyond `keep`; returns what was dropped
+fn evict(tracked: &mut TrackedLocations, now: DateTime<Utc>, keep: usize) -> Vec<String> {
+    let cutoff = now - Duration::days(TRACKING_DAYS);
+    let mut by_last_view: Vec<(DateTime<Utc>, String)> = tracked
+        .iter()
+        .map(|(location, entry)| (entry.last_viewed, location.clone()))
+        .collect();
+    by_last_view.sort();
+    let over_limit = tracked.len().saturating_sub(keep);
+    let evicted: Vec<String> = by_last_view
+        .into_iter()
+        .enumerate()
+        .take_while(|(i, (last_viewed, _))| *i < over_limit || *last_viewed <= cutoff)
+        .map(|(_, (_, location))| location)
+        .collect();
+    for location in &evicted {
+        tracked.remove(location);
+    }
+    evicted
+}
+
+// Forgets evicted locations in the store too, so they don't come back on restart
+async fn forget(evicted: Vec<String>) {
+    for location in evicted {
+        if let Err(e) = store::delete(TRACKED_NAMESPACE, &location).await {
+            tracing::warn!("Failed to forget tracked location: {}", e);
+        }
+    }
+}
+
+/// Records that someone looked at `location`, remembering the timezone they
+/// asked for if any.
+pub async fn touch(location: &str, timezone: Option<&str>) {
+    let now = Utc::now();
+    let mut tracked = TRACKED.write().await;
+    if tracked.is_none() {
+        *tracked = Some(load_tracked().await);
+    }
+    let Some(tracked) = tracked.as_mut() else {
+        return;
+    };
+
+    if !tracked.contains_key(location) && tracked.len() >= MAX_TRACKED_LOCATIONS {
+        // Makes room for this one
+        let evicted = evict(tracked, now, MAX_TRACKED_LOCATIONS - 1);
+        forget(evicted).await;
+    }
+    let entry = tracked
+        .entry(location.to_string())
+        .or_insert(TrackedLocation {
+            last_viewed: now,
+            timezone: None,
+            persisted_at: None,
+        });
+    entry.last_viewed = now;
+    if let Some(timezone) = timezone {
+        entry.timezone = Some(timezone.to_string());
+    }
+
+    let needs_persist = entry
+        .persisted_at
+        .is_none_or(|t| now.signed_duration_since(t).num_seconds() >= PERSIST_INTERVAL_SECONDS);
+    if needs_persist {
+        entry.persisted_at = Some(now);
+        let expires_at = now + Duration::days(TRACKING_DAYS);
+        if let Err(e) =
+            store::upsert(TRACKED_NAMESPACE, location, &*entry, Some(expires_at), None).await
+        {
+            tracing::warn!("Failed to save tracked location: {}", e);
+        }
+    }
+}
+
+/// Persists views that were only kept in memory so far, so a restart doesn't
+/// lose them.
+pub async fn flush() {
+    let mut tracked = TRACKED.write().await;
+    let Some(tracked) = tracked.as_mut() else {
+        return;
+    };
+    for (location, entry) in tracked.iter_mut() {
+        if entry.persisted_at.is_some_and(|t| t >= entry.last_viewed) {
+            continue;
+        }
+        entry.persisted_at = Some(entry.last_viewed);
+        let expires_at = entry.last_viewed + Duration::days(TRACKING_DAYS);
+        if let Err(e) =
+            store::upsert(TRACKED_NAMESPACE, location, &*entry, Some(expires_at), None).await
+        {
+            tracing::warn!("Failed to save tracked location: {}", e);
+        }
+    }
+}
+
+/// Configured locations plus everything viewed within the tracking window,
+/// with the latest view if there is one.
+pub async fn tracked_views() -> Vec<(String, Option<TrackedLocation>)> {
+    let now = Utc::now();
+    let mut tracked = TRACKED.write().await;
+    if tracked.is_none() {
+        *tracked = Some(load_tracked().await);
+    }
+    let Some(tracked) = tracked.as_mut() else {
+        return Vec::new();
+    };
+    let evicted = evict(tracked, now, MAX_TRACKED_LOCATIONS);
+    forget(evicted).await;
+
+    let mut locations: Vec<(String, Option<TrackedLocation>)> = configured_locations()
+        .into_iter()
+        .map(|location| (location, None))
+        .collect();
+    for (location, entry) in tracked.iter() {
+        match locations.iter_mut().find(|(l, _)| l == location) {
+            Some((_, view)) => *view = Some(entry.clone()),
+            None => locations.push((location.clone(), Some(entry.clone()))),
+        }
+    }
+    locations
+}
+
+/// Configured locations plus everything viewed within the tracking window.
+pub async fn tracked_locations() -> Vec<String> {
+    tracked_views()
+        .await
+        .into_iter()
+        .map(|(location, _)| location)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewed(days_ago: i64, now: DateTime<Utc>) -> TrackedLocation {
+        TrackedLocation {
+            last_viewed: now - Duration::days(days_ago),
+            timezone: None,
+            persisted_at: None,
+        }
+    }
+
+    #[test]
+    fn evicts_idle_then_least_recently_viewed() {
+        let now = Utc::now();
+        let mut tracked: TrackedLocations = [
+            ("Oslo", 0),
+            ("Bergen", 2),
+            ("Tromsø", 5),
+            ("Typo", TRACKING_DAYS + 1),
+        ]
+        .into_iter()
+        .map(|(location, days_ago)| (location.to_string(), viewed(days_ago, now)))
+        .collect();
+
+        assert_eq!(evict(&mut tracked, now, 10), ["Typo"]);
+        assert_eq!(evict(&mut tracked, now, 2), ["Tromsø"]);
+        let mut left: Vec<&String> = tracked.keys().collect();
+        left.sort();
+        assert_eq!(left, ["Bergen", "Oslo"]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// Consecutive failures after which calls to a host are short-circuited.
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open breaker rejects calls before letting a trial through.
+const OPEN_SECONDS: i64 = 60;
+
+static HOSTS: Lazy<Mutex<HashMap<String, UpstreamStatus>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    #[default]
+    Closed,
+    Open,
+    /// One trial call is allowed through to see if the host has recovered
+    HalfOpen,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UpstreamError {
+    pub time: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct UpstreamStatus {
+    pub host: String,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<UpstreamError>,
+    pub consecutive_failures: u32,
+    pub breaker: BreakerState,
+    #[serde(skip)]
+    opened_at: Option<DateTime<Utc>>,
+    // When the half-open trial went out. A trial whose future was dropped
+    // never reports back, so one older than OPEN_SECONDS counts as lost.
+    #[serde(skip)]
+    trial_started: Option<DateTime<Utc>>,
+}
+
+fn with_host<T>(host: &str, f: impl FnOnce(&mut UpstreamStatus) -> T) -> T {
+    let mut hosts = HOSTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let status = hosts
+        .entry(host.to_string())
+        .or_insert_with(|| UpstreamStatus {
+            host: host.to_string(),
+            ..Default::default()
+        });
+    f(status)
+}
+
+/// Whether a call to `host` may go out, given its circuit breaker.
+pub fn allow(host: &str) -> Result<(), String> {
+    with_host(host, |status| {
+        let now = Utc::now();
+        match status.breaker {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open
+                if status.opened_at.is_some_and(|t| {
+                    now.signed_duration_since(t).num_seconds() >= OPEN_SECONDS
+                }) =>
+            {
+                status.breaker = BreakerState::HalfOpen;
+                status.trial_started = Some(now);
+                Ok(())
+            }
+            BreakerState::HalfOpen
+                if status
+                    .trial_started
+                    .is_none_or(|t| now.signed_duration_since(t).num_seconds() >= OPEN_SECONDS) =>
+            {
+                status.trial_started = Some(now);
+                Ok(())
+            }
+            _ => Err(format!("Circuit breaker for {} is open", host)),
+        }
+    })
+}
+
+/// Hands back a half-open trial that `allow` let through but that never went
+/// out, so the next call can make it instead.
+pub fn cancel_trial(host: &str) {
+    with_host(host, |status| {
+        if status.breaker == BreakerState::HalfOpen {
+            status.trial_started = None;
+        }
+    });
+}
+
+pub fn record_success(host: &str) {
+    with_host(host, |status| {
+        status.last_success = Some(Utc::now());
+        status.consecutive_failures = 0;
+        status.breaker = BreakerState::Closed;
+        status.trial_started = None;
+    });
+}
+
+pub fn record_failure(host: &str, message: String) {
+    with_host(host, |status| {
+        let now = Utc::now();
+        status.last_error = Some(UpstreamError { time: now, message });
+        status.consecutive_failures += 1;
+        status.trial_started = None;
+        if status.breaker == BreakerState::HalfOpen
+            || status.consecutive_failures >= FAILURE_THRESHOLD
+        {
+            if status.breaker != BreakerState::Open {
+                tracing::warn!("Opening circuit breaker for {}", host);
+            }
+            status.breaker = BreakerState::Open;
+            status.opened_at = Some(now);
+        }
+    });
+}
+
+/// Every upstream host called since startup, by name.
+pub fn statuses() -> Vec<UpstreamStatus> {
+    let hosts = HOSTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut statuses: Vec<UpstreamStatus> = hosts.values().cloned().collect();
+    statuses.sort_by(|a, b| a.host.cmp(&b.host));
+    statuses
+}
@@ -0,0 +1,259 @@
+use chrono::{DateTime, Duration, FixedOffset};
+use chrono_tz::Tz;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::weather::PublicWeatherResponse;
+
+/// UV index from which the WHO recommends sun protection.
+const PROTECTION_THRESHOLD: f64 = 3.0;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UvCategory {
+    Low,
+    Moderate,
+    High,
+    VeryHigh,
+    Extreme,
+}
+
+impl UvCategory {
+    /// WHO UV index categories.
+    pub fn from_index(index: f64) -> Self {
+        match index.round() {
+            i if i >= 11.0 => UvCategory::Extreme,
+            i if i >= 8.0 => UvCategory::VeryHigh,
+            i if i >= 6.0 => UvCategory::High,
+            i if i >= 3.0 => UvCategory::Moderate,
+            _ => UvCategory::Low,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            UvCategory::Low => "low",
+            UvCategory::Moderate => "moderate",
+            UvCategory::High => "high",
+            UvCategory::VeryHigh => "very high",
+            UvCategory::Extreme => "extreme",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct UvHour {
+    pub time: DateTime<FixedOffset>,
+    pub clear_sky_uv_index: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_area_fraction: Option<f64>,
+    pub uv_index: f64,
+    pub category: UvCategory,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ProtectionWindow {
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct UvReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<UvHour>,
+    /// Highest estimated UV index for the rest of today
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak: Option<UvHour>,
+    /// When protection is needed today, if at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protection_window: Option<ProtectionWindow>,
+    pub hours: Vec<UvHour>,
+}
+
+// Cloud modification factor after Kasten & Czeplak. Even overcast skies let a
+// quarter of the UV through, which is what catches people out.
+fn cloud_modification_factor(cloud_area_fraction: f64) -> f64 {
+    let cover = (cloud_area_fraction / 100.0).clamp(0.0, 1.0);
+    1.0 - 0.75 * cover.powf(3.4)
+}
+
+fn uv_hours(weather: &PublicWeatherResponse, tz: &Tz) -> Vec<UvHour> {
+    weather
+        .properties
+        .timeseries
+        .iter()
+        .filter_map(|entry| {
+            let time = DateTime::parse_from_rfc3339(&entry.time)
+                .ok()?
+                .with_timezone(tz)
+                .fixed_offset();
+            let instant = entry.data.instant.details.as_ref();
+            // met.no only gives UV for the first days of the forecast
+            let clear_sky = instant
+                .and_then(|d| d.ultraviolet_index_clear_sky)
+                .or_else(|| {
+                    entry
+                        .data
+                        .next_1_hours
+                        .as_ref()
+                        .and_then(|f| f.details.as_ref())
+                        .and_then(|d| d.ultraviolet_index_clear_sky_max)
+                })?;
+            let cloud_area_fraction = instant.and_then(|d| d.cloud_area_fraction);
+            let uv_index = clear_sky * cloud_area_fraction.map_or(1.0, cloud_modification_factor);
+
+            Some(UvHour {
+                time,
+                clear_sky_uv_index: clear_sky,
+                cloud_area_fraction,
+                uv_index,
+                category: UvCategory::from_index(uv_index),
+            })
+        })
+        .collect()
+}
+
+// From the first hour needing protection to the end of the last one
+fn protection_window(hours: &[&UvHour]) -> Option<ProtectionWindow> {
+    let mut needs_protection = hours
+        .iter()
+        .filter(|h| h.uv_index.round() >= PROTECTION_THRESHOLD);
+    let first = needs_protection.next()?;
+    let last = needs_protection.next_back().unwrap_or(first);
+    Some(ProtectionWindow {
+        from: first.time,
+        to: last.time + Duration::hours(1),
+    })
+}
+
+pub fn uv_report(weather: &PublicWeatherResponse, tz: &Tz) -> UvReport {
+    let hours = uv_hours(weather, tz);
+    let today = chrono::Utc::now().with_timezone(tz).date_naive();
+    let rest_of_today: Vec<&UvHour> = hours
+        .iter()
+        .filter(|h| h.time.date_naive() == today)
+        .collect();
+
+    let peak = rest_of_today
+        .iter()
+        .max_by(|a, b| a.uv_index.total_cmp(&b.uv_index))
+        .map(|h| (*h).clone());
+
+    let protection_window = protection_window(&rest_of_today);
+
+    UvReport {
+        current: hours.first().cloned(),
+        peak,
+        protection_window,
+        hours,
+    }
+}
+
+/// A line for the summary prompt, only when the UV is moderate or above.
+pub fn prompt_line(weather: &PublicWeatherResponse, tz: &Tz) -> Option<String> {
+    let report = uv_report(weather, tz);
+    let peak = report.peak?;
+    if peak.category < UvCategory::Moderate {
+        return None;
+    }
+    let window = report
+        .protection_window
+        .map(|w| {
+            format!(
+                ", sun protection needed {}-{}",
+                w.from.format("%H:%M"),
+                w.to.format("%H:%M")
+            )
+        })
+        .unwrap_or_default();
+    Some(format!(
+        "UV index today: peaks at {:.0} ({}){}",
+        peak.uv_index,
+        peak.category.label(),
+        window
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hour(time: &str, uv_index: f64) -> UvHour {
+        UvHour {
+            time: DateTime::parse_from_rfc3339(time).unwrap(),
+            clear_sky_uv_index: uv_index,
+            cloud_area_fraction: None,
+            uv_index,
+            category: UvCategory::from_index(uv_index),
+        }
+    }
+
+    #[test]
+    fn categories_round_to_who_edges() {
+        let cases = [
+            (0.0, UvCategory::Low),
+            (2.49, UvCategory::Low),
+            (2.5, UvCategory::Moderate),
+            (5.49, UvCategory::Moderate),
+            (5.5, UvCategory::High),
+            (7.49, UvCategory::High),
+            (7.5, UvCategory::VeryHigh),
+            (10.49, UvCategory::VeryHigh),
+            (10.5, UvCategory::Extreme),
+            (14.0, UvCategory::Extreme),
+        ];
+        for (index, category) in cases {
+            assert_eq!(
+                UvCategory::from_index(index),
+                category,
+                "UV index {}",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn clouds_let_a_quarter_through() {
+        assert_eq!(cloud_modification_factor(0.0), 1.0);
+        assert_eq!(cloud_modification_factor(100.0), 0.25);
+        assert_eq!(cloud_modification_factor(150.0), 0.25);
+        let half = cloud_modification_factor(50.0);
+        assert!(half > 0.9 && half < 1.0);
+    }
+
+    #[test]
+    fn protection_window_spans_first_to_end_of_last_hour() {
+        let hours = [
+            hour("2026-06-21T09:00:00+02:00", 2.0),
+            hour("2026-06-21T10:00:00+02:00", 2.5),
+            hour("2026-06-21T11:00:00+02:00", 5.0),
+            // A cloudy hour in between doesn't split the window
+            hour("2026-06-21T12:00:00+02:00", 1.0),
+            hour("2026-06-21T13:00:00+02:00", 4.0),
+            hour("2026-06-21T14:00:00+02:00", 2.4),
+        ];
+        let hours: Vec<&UvHour> = hours.iter().collect();
+        let window = protection_window(&hours).unwrap();
+        assert_eq!(window.from.to_rfc3339(), "2026-06-21T10:00:00+02:00");
+        assert_eq!(window.to.to_rfc3339(), "2026-06-21T14:00:00+02:00");
+    }
+
+    #[test]
+    fn protection_window_of_a_single_hour() {
+        let hours = [hour("2026-06-21T13:00:00+02:00", 3.0)];
+        let hours: Vec<&UvHour> = hours.iter().collect();
+        let window = protection_window(&hours).unwrap();
+        assert_eq!(window.from.to_rfc3339(), "2026-06-21T13:00:00+02:00");
+        assert_eq!(window.to.to_rfc3339(), "2026-06-21T14:00:00+02:00");
+    }
+
+    #[test]
+    fn no_protection_window_on_low_days() {
+        let hours = [
+            hour("2026-12-21T12:00:00+01:00", 0.4),
+            hour("2026-12-21T13:00:00+01:00", 2.49),
+        ];
+        let hours: Vec<&UvHour> = hours.iter().collect();
+        assert!(protection_window(&hours).is_none());
+    }
+}
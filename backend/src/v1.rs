@@ -0,0 +1,816 @@
+use axum::{
+    Json, Router,
+    body::to_bytes,
+    extract::{Path, Query, Request},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{MethodRouter, get, patch, post, put},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::{ToResponse, ToSchema};
+
+use crate::{
+    AccuracyQuery, AstronomyQuery, CachedJson, HistoryQuery, WeatherQuery, WeatherSummary,
+    accuracy::{AccuracyReport, Observation},
+    air_quality::AirQualityReport,
+    alerts::WeatherAlert,
+    astronomy::AstronomyReport,
+    auth, dashboards,
+    history::WeatherHistory,
+    places, ratelimit,
+    uv::UvReport,
+    weather::{self, ForecastDetails, MarineForecast, PublicWeatherResponse},
+};
+
+const PREFIX: &str = "/api/v1/";
+// Error bodies are short messages; anything longer is cut off
+const MAX_ERROR_BYTES: usize = 64 * 1024;
+
+/// The error body of every v1 route.
+#[derive(Serialize, ToSchema, ToResponse)]
+#[response(description = "What went wrong")]
+pub struct ApiError {
+    /// The HTTP status code again
+    pub status: u16,
+    pub error: String,
+}
+
+/// Where a forecast is for. Elevation in m.
+#[derive(Serialize, ToSchema)]
+pub struct Location {
+    /// The place name as asked for
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation_m: Option<f64>,
+}
+
+/// What the sky is doing, independent of any provider's symbol set.
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Conditions {
+    Clear,
+    MostlyClear,
+    PartlyCloudy,
+    Cloudy,
+    Fog,
+    Rain,
+    Sleet,
+    Snow,
+}
+
+/// The weather at `time`, and over the period starting then. Instant values
+/// (temperature, wind, ...) are for `time` itself; conditions, precipitation
+/// and the temperature range cover the next `period_hours`.
+#[derive(Serialize, ToSchema)]
+pub struct ForecastStep {
+    pub time: DateTime<Utc>,
+    pub temperature_c: Option<f64>,
+    pub dew_point_c: Option<f64>,
+    pub humidity_percent: Option<f64>,
+    pub pressure_hpa: Option<f64>,
+    pub cloud_cover_percent: Option<f64>,
+    pub fog_percent: Option<f64>,
+    /// UV index under a clear sky
+    pub uv_index: Option<f64>,
+    pub wind_speed_ms: Option<f64>,
+    pub wind_gust_ms: Option<f64>,
+    /// Where the wind comes from; 0 is north, 90 east
+    pub wind_direction_deg: Option<f64>,
+    /// 1, 6 or 12: the shortest period the provider has for this step
+    pub period_hours: Option<u32>,
+    pub conditions: Option<Conditions>,
+    pub thunder: bool,
+    /// Whether the sun is up, if the provider says
+    pub daylight: Option<bool>,
+    /// The conditions in English, e.g. "Light rain showers"
+    pub description: Option<String>,
+    pub precipitation_mm: Option<f64>,
+    pub precipitation_min_mm: Option<f64>,
+    pub precipitation_max_mm: Option<f64>,
+    pub precipitation_probability_percent: Option<f64>,
+    pub thunder_probability_percent: Option<f64>,
+    pub temperature_min_c: Option<f64>,
+    pub temperature_max_c: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Forecast {
+    pub location: Location,
+    /// Who made the forecast, e.g. "met.no"
+    pub provider: String,
+    /// When the provider last updated it
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Hourly at first, then further apart
+    pub steps: Vec<ForecastStep>,
+}
+
+/// Sea conditions at one point in time.
+#[derive(Serialize, ToSchema)]
+pub struct MarineStep {
+    pub time: DateTime<Utc>,
+    pub wave_height_m: Option<f64>,
+    /// Where the waves come from; 0 is north
+    pub wave_direction_deg: Option<f64>,
+    pub water_temperature_c: Option<f64>,
+    pub current_speed_ms: Option<f64>,
+    /// Where the current flows to; 0 is north
+    pub current_direction_deg: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Marine {
+    /// False for places the ocean model doesn't reach
+    pub covered: bool,
+    pub reason: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub steps: Vec<MarineStep>,
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// met.no symbols are a condition, optionally with a day/night variant, e.g.
+// "lightrainshowersandthunder_day"
+fn met_conditions(symbol_code: &str) -> (Option<Conditions>, bool, Option<bool>) {
+    let (code, variant) = symbol_code.split_once('_').unwrap_or((symbol_code, ""));
+    let conditions = match code {
+        "clearsky" => Some(Conditions::Clear),
+        "fair" => Some(Conditions::MostlyClear),
+        "partlycloudy" => Some(Conditions::PartlyCloudy),
+        "cloudy" => Some(Conditions::Cloudy),
+        "fog" => Some(Conditions::Fog),
+        code if code.contains("sleet") => Some(Conditions::Sleet),
+        code if code.contains("snow") => Some(Conditions::Snow),
+        code if code.contains("rain") => Some(Conditions::Rain),
+        _ => None,
+    };
+    let daylight = match variant {
+        "day" => Some(true),
+        "night" | "polartwilight" => Some(false),
+        _ => None,
+    };
+    (conditions, code.contains("thunder"), daylight)
+}
+
+impl Forecast {
+    pub fn from_met(name: String, forecast: PublicWeatherResponse) -> Forecast {
+        let [longitude, latitude, elevation] = forecast.geometry.coordinates;
+        let steps = forecast
+            .properties
+            .timeseries
+            .into_iter()
+            .filter_map(|entry| {
+                let time = parse_time(&entry.time)?;
+                let instant = entry.data.instant.details.unwrap_or_default();
+                let periods: [(u32, Option<ForecastDetails>); 3] = [
+                    (1, entry.data.next_1_hours),
+                    (6, entry.data.next_6_hours),
+                    (12, entry.data.next_12_hours),
+                ];
+                let period = periods
+                    .into_iter()
+                    .find_map(|(hours, period)| period.map(|period| (hours, period)));
+                let symbol = period
+                    .as_ref()
+                    .map(|(_, period)| period.summary.symbol_code.as_str());
+                let (conditions, thunder, daylight) =
+                    symbol.map_or((None, false, None), met_conditions);
+                let description = symbol.map(weather::describe_symbol);
+                let details = period
+                    .as_ref()
+                    .and_then(|(_, period)| period.details.as_ref());
+                let detail = |field: fn(&weather::ForecastTimePeriod) -> Option<f64>| {
+                    details.and_then(field)
+                };
+
+                Some(ForecastStep {
+                    time,
+                    temperature_c: instant.air_temperature,
+                    dew_point_c: instant.dew_point_temperature,
+                    humidity_percent: instant.relative_humidity,
+                    pressure_hpa: instant.air_pressure_at_sea_level,
+                    cloud_cover_percent: instant.cloud_area_fraction,
+                    fog_percent: instant.fog_area_fraction,
+                    uv_index: instant.ultraviolet_index_clear_sky,
+                    wind_speed_ms: instant.wind_speed,
+                    wind_gust_ms: instant.wind_speed_of_gust,
+                    wind_direction_deg: instant.wind_from_direction,
+                    period_hours: period.as_ref().map(|(hours, _)| *hours),
+                    conditions,
+                    thunder,
+                    daylight,
+                    description,
+                    precipitation_mm: detail(|d| d.precipitation_amount),
+                    precipitation_min_mm: detail(|d| d.precipitation_amount_min),
+                    precipitation_max_mm: detail(|d| d.precipitation_amount_max),
+                    precipitation_probability_percent: detail(|d| d.probability_of_precipitation),
+                    thunder_probability_percent: detail(|d| d.probability_of_thunder),
+                    temperature_min_c: detail(|d| d.air_temperature_min),
+                    temperature_max_c: detail(|d| d.air_temperature_max),
+                })
+            })
+            .collect();
+
+        Forecast {
+            location: Location {
+                name,
+                latitude,
+                longitude,
+                elevation_m: Some(elevation),
+            },
+            provider: "met.no".to_string(),
+            updated_at: parse_time(&forecast.properties.meta.updated_at),
+            steps,
+        }
+    }
+}
+
+impl From<MarineForecast> for Marine {
+    fn from(forecast: MarineForecast) -> Marine {
+        Marine {
+            covered: forecast.covered,
+            reason: forecast.reason,
+            updated_at: forecast.updated_at.as_deref().and_then(parse_time),
+            steps: forecast
+                .timeseries
+                .into_iter()
+                .filter_map(|conditions| {
+                    let details = conditions.details;
+                    Some(MarineStep {
+                        time: parse_time(&conditions.time)?,
+                        wave_height_m: details.sea_surface_wave_height,
+                        wave_direction_deg: details.sea_surface_wave_from_direction,
+                        water_temperature_c: details.sea_water_temperature,
+                        current_speed_ms: details.sea_water_speed,
+                        current_direction_deg: details.sea_water_to_direction,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Gives v1 routes JSON error bodies, whether the error came from a handler
+/// or from middleware such as auth and rate limiting, which send plain text.
+pub async fn json_errors(request: Request, next: Next) -> Response {
+    if !request.uri().path().starts_with(PREFIX) {
+        return next.run(request).await;
+    }
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let error = match to_bytes(body, MAX_ERROR_BYTES).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => status.canonical_reason().unwrap_or("Error").to_string(),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+    let body = Json(ApiError {
+        status: status.as_u16(),
+        error,
+    });
+    (parts, body).into_response()
+}
+
+/// Coordinates of a place name.
+#[utoipa::path(
+    get,
+    operation_id = "getLocation",
+    path = "/api/v1/locations/{location}",
+    tag = "locations",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Location),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_location(
+    Path(location): Path<String>,
+) -> Result<CachedJson<Location>, (StatusCode, String)> {
+    let (headers, Json(coords)) = crate::handle_location(Path(location.clone())).await?;
+    Ok((
+        headers,
+        Json(Location {
+            name: location,
+            latitude: coords.lat(),
+            longitude: coords.lon(),
+            elevation_m: None,
+        }),
+    ))
+}
+
+/// Forecast for the next days.
+#[utoipa::path(
+    get,
+    operation_id = "getForecast",
+    path = "/api/v1/weather/{location}",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Forecast),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_forecast(
+    Path(location): Path<String>,
+) -> Result<CachedJson<Forecast>, (StatusCode, String)> {
+    let (headers, Json(forecast)) = crate::handle_weather(Path(location.clone())).await?;
+    Ok((headers, Json(Forecast::from_met(location, forecast))))
+}
+
+/// The weather right now: the first step of the forecast.
+#[utoipa::path(
+    get,
+    operation_id = "getCurrentConditions",
+    path = "/api/v1/weather/{location}/current",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = ForecastStep),
+        (status = 404, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_current(
+    Path(location): Path<String>,
+) -> Result<CachedJson<ForecastStep>, (StatusCode, String)> {
+    let (headers, Json(forecast)) = handle_forecast(Path(location)).await?;
+    match forecast.steps.into_iter().next() {
+        Some(step) => Ok((headers, Json(step))),
+        None => Err((StatusCode::NOT_FOUND, "No forecast data".to_string())),
+    }
+}
+
+/// Short written summary of the forecast and active alerts.
+#[utoipa::path(
+    get,
+    operation_id = "getWeatherSummary",
+    path = "/api/v1/weather/{location}/summary",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), WeatherQuery),
+    responses(
+        (status = 200, body = WeatherSummary),
+        (status = 400, response = ApiError),
+        (status = 429, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_summary(
+    location: Path<String>,
+    query: Query<WeatherQuery>,
+) -> Result<CachedJson<WeatherSummary>, (StatusCode, String)> {
+    crate::handle_summarize_weather(location, query).await
+}
+
+/// Active weather alerts, most severe first.
+#[utoipa::path(
+    get,
+    operation_id = "getWeatherAlerts",
+    path = "/api/v1/weather/{location}/alerts",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Vec<WeatherAlert>),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_alerts(
+    location: Path<String>,
+) -> Result<CachedJson<Vec<WeatherAlert>>, (StatusCode, String)> {
+    crate::handle_weather_alerts(location).await
+}
+
+/// UV index by hour and when to protect yourself.
+#[utoipa::path(
+    get,
+    operation_id = "getUv",
+    path = "/api/v1/weather/{location}/uv",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), WeatherQuery),
+    responses(
+        (status = 200, body = UvReport),
+        (status = 400, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_uv(
+    location: Path<String>,
+    query: Query<WeatherQuery>,
+) -> Result<CachedJson<UvReport>, (StatusCode, String)> {
+    crate::handle_uv(location, query).await
+}
+
+/// Recorded conditions over a time range.
+#[utoipa::path(
+    get,
+    operation_id = "getWeatherHistory",
+    path = "/api/v1/weather/{location}/history",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), HistoryQuery),
+    responses(
+        (status = 200, body = WeatherHistory),
+        (status = 400, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_history(
+    location: Path<String>,
+    query: Query<HistoryQuery>,
+) -> Result<Json<WeatherHistory>, (StatusCode, String)> {
+    crate::handle_weather_history(location, query).await
+}
+
+/// How well the forecasts matched what happened.
+#[utoipa::path(
+    get,
+    operation_id = "getWeatherAccuracy",
+    path = "/api/v1/weather/{location}/accuracy",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), AccuracyQuery),
+    responses(
+        (status = 200, body = AccuracyReport),
+        (status = 400, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_accuracy(
+    location: Path<String>,
+    query: Query<AccuracyQuery>,
+) -> Result<Json<AccuracyReport>, (StatusCode, String)> {
+    crate::handle_weather_accuracy(location, query).await
+}
+
+/// Records a measurement to verify forecasts against. Needs the write scope.
+#[utoipa::path(
+    post,
+    operation_id = "recordObservation",
+    path = "/api/v1/weather/{location}/observations",
+    tag = "weather",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    request_body = Observation,
+    responses(
+        (status = 204, description = "Recorded"),
+        (status = 401, response = ApiError),
+        (status = 403, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_observation(
+    location: Path<String>,
+    observation: Json<Observation>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    crate::handle_weather_observation(location, observation).await
+}
+
+/// Sun and moon times for a day.
+#[utoipa::path(
+    get,
+    operation_id = "getAstronomy",
+    path = "/api/v1/astronomy/{location}",
+    tag = "astronomy",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo"), AstronomyQuery),
+    responses(
+        (status = 200, body = AstronomyReport),
+        (status = 400, response = ApiError),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_astronomy(
+    location: Path<String>,
+    query: Query<AstronomyQuery>,
+) -> Result<Json<AstronomyReport>, (StatusCode, String)> {
+    crate::handle_astronomy(location, query).await
+}
+
+/// Air quality and pollen forecast.
+#[utoipa::path(
+    get,
+    operation_id = "getAirQuality",
+    path = "/api/v1/air-quality/{location}",
+    tag = "air quality",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = AirQualityReport),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_air_quality(
+    location: Path<String>,
+) -> Result<CachedJson<AirQualityReport>, (StatusCode, String)> {
+    crate::handle_air_quality(location).await
+}
+
+/// Waves, currents and sea temperature.
+#[utoipa::path(
+    get,
+    operation_id = "getMarine",
+    path = "/api/v1/marine/{location}",
+    tag = "marine",
+    params(("location" = String, Path, description = "Place name, e.g. Oslo")),
+    responses(
+        (status = 200, body = Marine),
+        (status = 500, response = ApiError)
+    )
+)]
+async fn handle_marine(location: Path<String>) -> Result<CachedJson<Marine>, (StatusCode, String)> {
+    let (headers, Json(forecast)) = crate::handle_marine(location).await?;
+    Ok((headers, Json(forecast.into())))
+}
+
+// Routes that change saved state, which need the write scope
+fn writes(route: MethodRouter) -> MethodRouter {
+    route.route_layer(middleware::from_fn(auth::require_write))
+}
+
+/// The versioned API, to nest under `/api/v1` inside the API's auth and rate
+/// limits. Its schema is ours rather than a provider's: timestamps are
+/// parsed, fields are flat and carry their unit in the name, and errors are
+/// JSON. Changes to it stay backwards compatible; anything else is for a v2.
+pub fn router() -> Router {
+    Router::new()
+        .route("/locations/{location}", get(handle_location))
+        .route(
+            "/dashboards",
+            get(dashboards::handle_list_dashboards)
+                .merge(writes(post(dashboards::handle_create_dashboard))),
+        )
+        .route("/dashboards/widgets", get(dashboards::handle_widget_types))
+        .route(
+            "/dashboards/{name}",
+            get(dashboards::handle_get_dashboard).merge(writes(
+                put(dashboards::handle_put_dashboard).delete(dashboards::handle_delete_dashboard),
+            )),
+        )
+        .route(
+            "/places",
+            get(places::handle_list_places).merge(writes(post(places::handle_create_place))),
+        )
+        .route("/places/order", writes(put(places::handle_reorder_places)))
+        .route(
+            "/places/{id}",
+            get(places::handle_get_place).merge(writes(
+                patch(places::handle_update_place).delete(places::handle_delete_place),
+            )),
+        )
+        .route("/weather/batch", get(places::handle_batch_weather))
+        .route("/weather/{location}", get(handle_forecast))
+        .route("/weather/{location}/current", get(handle_current))
+        .route(
+            "/weather/{location}/summary",
+            get(handle_summary).route_layer(middleware::from_fn(ratelimit::limit_summary)),
+        )
+        .route("/weather/{location}/alerts", get(handle_alerts))
+        .route("/weather/{location}/uv", get(handle_uv))
+        .route("/weather/{location}/history", get(handle_history))
+        .route("/weather/{location}/accuracy", get(handle_accuracy))
+        .route(
+            "/weather/{location}/observations",
+            writes(post(handle_observation)),
+        )
+        .route("/astronomy/{location}", get(handle_astronomy))
+        .route("/air-quality/{location}", get(handle_air_quality))
+        .route("/marine/{location}", get(handle_marine))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::weather::{Geometry, PublicProperties};
+
+    fn met_forecast(timeseries: Value) -> PublicWeatherResponse {
+        PublicWeatherResponse {
+            response_type: "Feature".to_string(),
+            geometry: Geometry {
+                coordinates: [10.7522, 59.9139, 23.0],
+                geometry_type: "Point".to_string(),
+            },
+            properties: PublicProperties {
+                meta: serde_json::from_value(json!({
+                    "units": {},
+                    "updated_at": "2026-10-18T10:12:00Z"
+                }))
+                .unwrap(),
+                timeseries: serde_json::from_value(timeseries).unwrap(),
+            },
+        }
+    }
+
+    fn period(symbol_code: &str, precipitation_amount: f64) -> Value {
+        json!({
+            "summary": { "symbol_code": symbol_code },
+            "details": { "precipitation_amount": precipitation_amount }
+        })
+    }
+
+    #[test]
+    fn day_night_and_polar_twilight_variants() {
+        assert_eq!(
+            met_conditions("clearsky_day"),
+            (Some(Conditions::Clear), false, Some(true))
+        );
+        assert_eq!(
+            met_conditions("fair_night"),
+            (Some(Conditions::MostlyClear), false, Some(false))
+        );
+        assert_eq!(
+            met_conditions("partlycloudy_polartwilight"),
+            (Some(Conditions::PartlyCloudy), false, Some(false))
+        );
+        assert_eq!(
+            met_conditions("cloudy"),
+            (Some(Conditions::Cloudy), false, None)
+        );
+        assert_eq!(met_conditions("fog"), (Some(Conditions::Fog), false, None));
+    }
+
+    #[test]
+    fn sleet_is_not_rain_or_snow() {
+        for symbol in ["sleet", "lightsleetshowers_day", "heavysleet"] {
+            assert_eq!(
+                met_conditions(symbol).0,
+                Some(Conditions::Sleet),
+                "{}",
+                symbol
+            );
+        }
+        for symbol in ["rain", "lightrainshowers_night", "heavyrain"] {
+            assert_eq!(
+                met_conditions(symbol).0,
+                Some(Conditions::Rain),
+                "{}",
+                symbol
+            );
+        }
+        for symbol in ["snow", "heavysnowshowers_polartwilight"] {
+            assert_eq!(
+                met_conditions(symbol).0,
+                Some(Conditions::Snow),
+                "{}",
+                symbol
+            );
+        }
+        assert_eq!(met_conditions("somethingnew").0, None);
+    }
+
+    #[test]
+    fn thunder_comes_with_its_precipitation() {
+        assert_eq!(
+            met_conditions("heavyrainshowersandthunder_day"),
+            (Some(Conditions::Rain), true, Some(true))
+        );
+        // met.no spells this one with a double s
+        assert_eq!(
+            met_conditions("lightssleetshowersandthunder_night"),
+            (Some(Conditions::Sleet), true, Some(false))
+        );
+        assert_eq!(
+            met_conditions("snowandthunder"),
+            (Some(Conditions::Snow), true, None)
+        );
+    }
+
+    #[test]
+    fn steps_use_the_shortest_period() {
+        let forecast = met_forecast(json!([
+            {
+                "time": "2026-10-18T12:00:00Z",
+                "data": {
+                    "instant": { "details": { "air_temperature": 8.5 } },
+                    "next_1_hours": period("rain", 0.4),
+                    "next_6_hours": period("cloudy", 1.2),
+                    "next_12_hours": period("fair_day", 1.5)
+                }
+            },
+            {
+                "time": "2026-10-21T12:00:00Z",
+                "data": {
+                    "instant": { "details": {} },
+                    "next_6_hours": period("snow", 3.0),
+                    "next_12_hours": period("cloudy", 4.0)
+                }
+            },
+            {
+                "time": "2026-10-26T00:00:00Z",
+                "data": {
+                    "instant": { "details": {} },
+                    "next_12_hours": { "summary": { "symbol_code": "clearsky_night" } }
+                }
+            },
+            {
+                "time": "2026-10-28T00:00:00Z",
+                "data": { "instant": {} }
+            },
+            {
+                "time": "not a time",
+                "data": { "instant": {} }
+            }
+        ]));
+        let forecast = Forecast::from_met("Oslo".to_string(), forecast);
+
+        let steps = &forecast.steps;
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].period_hours, Some(1));
+        assert_eq!(steps[0].conditions, Some(Conditions::Rain));
+        assert_eq!(steps[0].precipitation_mm, Some(0.4));
+        assert_eq!(steps[1].period_hours, Some(6));
+        assert_eq!(steps[1].conditions, Some(Conditions::Snow));
+        assert_eq!(steps[1].precipitation_mm, Some(3.0));
+        assert_eq!(steps[2].period_hours, Some(12));
+        assert_eq!(steps[2].daylight, Some(false));
+        assert_eq!(steps[2].precipitation_mm, None);
+        assert_eq!(steps[3].period_hours, None);
+        assert_eq!(steps[3].conditions, None);
+        assert_eq!(steps[3].description, None);
+    }
+
+    // The schema clients build on; changing this test means a breaking change
+    #[test]
+    fn forecast_json_is_flat() {
+        let forecast = met_forecast(json!([{
+            "time": "2026-10-18T12:00:00Z",
+            "data": {
+                "instant": {
+                    "details": {
+                        "air_temperature": 8.5,
+                        "air_pressure_at_sea_level": 1012.3,
+                        "cloud_area_fraction": 90.0,
+                        "dew_point_temperature": 6.1,
+                        "fog_area_fraction": 0.0,
+                        "relative_humidity": 85.0,
+                        "ultraviolet_index_clear_sky": 0.5,
+                        "wind_from_direction": 200.0,
+                        "wind_speed": 4.2,
+                        "wind_speed_of_gust": 9.8
+                    }
+                },
+                "next_1_hours": {
+                    "summary": { "symbol_code": "lightrainshowersandthunder_day" },
+                    "details": {
+                        "precipitation_amount": 0.4,
+                        "precipitation_amount_min": 0.1,
+                        "precipitation_amount_max": 0.9,
+                        "probability_of_precipitation": 60.0,
+                        "probability_of_thunder": 10.0
+                    }
+                }
+            }
+        }]));
+        let forecast = Forecast::from_met("Oslo".to_string(), forecast);
+
+        assert_eq!(
+            serde_json::to_value(&forecast).unwrap(),
+            json!({
+                "location": {
+                    "name": "Oslo",
+                    "latitude": 59.9139,
+                    "longitude": 10.7522,
+                    "elevation_m": 23.0
+                },
+                "provider": "met.no",
+                "updated_at": "2026-10-18T10:12:00Z",
+                "steps": [{
+                    "time": "2026-10-18T12:00:00Z",
+                    "temperature_c": 8.5,
+                    "dew_point_c": 6.1,
+                    "humidity_percent": 85.0,
+                    "pressure_hpa": 1012.3,
+                    "cloud_cover_percent": 90.0,
+                    "fog_percent": 0.0,
+                    "uv_index": 0.5,
+                    "wind_speed_ms": 4.2,
+                    "wind_gust_ms": 9.8,
+                    "wind_direction_deg": 200.0,
+                    "period_hours": 1,
+                    "conditions": "rain",
+                    "thunder": true,
+                    "daylight": true,
+                    "description": "Light rain showers and thunder",
+                    "precipitation_mm": 0.4,
+                    "precipitation_min_mm": 0.1,
+                    "precipitation_max_mm": 0.9,
+                    "precipitation_probability_percent": 60.0,
+                    "thunder_probability_percent": 10.0,
+                    "temperature_min_c": null,
+                    "temperature_max_c": null
+                }]
+            })
+        );
+    }
+}
@@ -2,11 +2,23 @@ use chrono_tz::Tz;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
-
-use crate::location::Coordinates;
-
-const WEATHER_CACHE_FILE: &str = "cache/weather_cache.json";
-const WEATHER_SUMMARY_CACHE_FILE: &str = "cache/weather_summary_cache.json";
+use utoipa::ToSchema;
+
+use crate::{
+    alerts::WeatherAlert,
+    astronomy::{self, PolarCondition},
+    events::{self, EventKind},
+    location::Coordinates,
+    metrics::{self, CacheResult, TimedSend},
+    ratelimit, store, uv,
+};
+
+pub const WEATHER_NAMESPACE: &str = "weather";
+pub const WEATHER_SUMMARY_NAMESPACE: &str = "weather_summary";
+pub const OCEAN_NAMESPACE: &str = "ocean";
+pub const CACHE_SECONDS: i64 = 600;
+const MAX_SUMMARY_CACHE_ENTRIES: usize = 100;
+const SUMMARY_MODEL: &str = "gemma-3-27b-it";
 
 static WEATHER_CACHE: Lazy<RwLock<WeatherCache>> = Lazy::new(|| {
     RwLock::new(WeatherCache {
@@ -18,15 +30,20 @@ static WEATHER_SUMMARY_CACHE: Lazy<RwLock<WeatherSummaryCache>> = Lazy::new(|| {
         cache: std::collections::HashMap::new(),
     })
 });
+static OCEAN_CACHE: Lazy<RwLock<OceanCache>> = Lazy::new(|| {
+    RwLock::new(OceanCache {
+        cache: std::collections::HashMap::new(),
+    })
+});
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct Geometry {
     pub coordinates: [f64; 3],
     #[serde(rename = "type")]
     pub geometry_type: String,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, Default, ToSchema)]
 pub struct ForecastTimeInstant {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub air_pressure_at_sea_level: Option<f64>,
@@ -47,6 +64,8 @@ pub struct ForecastTimeInstant {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relative_humidity: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub ultraviolet_index_clear_sky: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub wind_from_direction: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub wind_speed: Option<f64>,
@@ -54,7 +73,7 @@ pub struct ForecastTimeInstant {
     pub wind_speed_of_gust: Option<f64>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct ForecastTimePeriod {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub air_temperature_max: Option<f64>,
@@ -74,24 +93,24 @@ pub struct ForecastTimePeriod {
     pub ultraviolet_index_clear_sky_max: Option<f64>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct ForecastSummary {
     pub symbol_code: String,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct ForecastDetails {
     pub summary: ForecastSummary,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<ForecastTimePeriod>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct InstantDetails {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<ForecastTimeInstant>,
 }
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct TimeSeriesData {
     pub instant: InstantDetails,
     #[serde(rename = "next_1_hours")]
@@ -102,13 +121,13 @@ pub struct TimeSeriesData {
     pub next_12_hours: Option<ForecastDetails>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct TimeSeriesEntry {
     pub time: String,
     pub data: TimeSeriesData,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct ForecastUnits {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub air_pressure_at_sea_level: Option<String>,
@@ -143,6 +162,8 @@ pub struct ForecastUnits {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relative_humidity: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub ultraviolet_index_clear_sky: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ultraviolet_index_clear_sky_max: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub wind_from_direction: Option<String>,
@@ -152,7 +173,7 @@ pub struct ForecastUnits {
     pub wind_speed_of_gust: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 pub struct ForecastMeta {
     pub units: ForecastUnits,
     pub updated_at: String,
@@ -187,6 +208,29 @@ pub struct WeatherCache {
 pub struct WeatherSummaryCacheItem {
     pub time: chrono::DateTime<chrono::Utc>,
     pub summary: String,
+    /// What the summary was written from; left out for hand-written ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub basis: Option<SummaryBasis>,
+}
+
+/// The forecast run and alerts a summary talks about. While neither changes,
+/// a new summary would say the same thing.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SummaryBasis {
+    pub forecast_updated_at: String,
+    pub alert_ids: Vec<String>,
+}
+
+impl SummaryBasis {
+    fn new(weather: &PublicWeatherResponse, alerts: &[WeatherAlert]) -> Self {
+        let mut alert_ids: Vec<String> = alerts.iter().map(|alert| alert.id.clone()).collect();
+        alert_ids.sort();
+        alert_ids.dedup();
+        SummaryBasis {
+            forecast_updated_at: weather.properties.meta.updated_at.clone(),
+            alert_ids,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -195,89 +239,73 @@ pub struct WeatherSummaryCache {
 }
 
 async fn load_weather_cache() -> Result<WeatherCache, Box<dyn std::error::Error + Send + Sync>> {
-    match tokio::fs::read_to_string(WEATHER_CACHE_FILE).await {
-        Ok(data) => {
-            let cache: WeatherCache = serde_json::from_str(&data)?;
-            Ok(cache)
-        }
-        Err(_) => Ok(WeatherCache {
-            cache: std::collections::HashMap::new(),
-        }),
-    }
+    Ok(WeatherCache {
+        cache: store::load_namespace(WEATHER_NAMESPACE).await?,
+    })
 }
 
 async fn clear_useless_weather_cache(
     cache: &mut WeatherCache,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let current_time = chrono::Utc::now();
-    cache
-        .cache
-        .retain(|_, entry| current_time.signed_duration_since(entry.time).num_seconds() < 600);
+    cache.cache.retain(|_, entry| {
+        current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS
+    });
     Ok(())
 }
 
-async fn save_weather_cache(
-    cache: &WeatherCache,
+async fn save_weather_cache_entry(
+    key: &str,
+    entry: &WeatherCacheItem,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let data = serde_json::to_string(cache)?;
-    match tokio::fs::create_dir_all("cache").await {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to create cache directory: {}", e);
-            return Err(Box::new(e));
-        }
-    }
-    tokio::fs::write(WEATHER_CACHE_FILE, data).await?;
-    Ok(())
+    let expires_at = entry.time + chrono::Duration::seconds(CACHE_SECONDS);
+    store::upsert(WEATHER_NAMESPACE, key, entry, Some(expires_at), None).await
 }
 
 async fn load_weather_summary_cache()
 -> Result<WeatherSummaryCache, Box<dyn std::error::Error + Send + Sync>> {
-    match tokio::fs::read_to_string(WEATHER_SUMMARY_CACHE_FILE).await {
-        Ok(data) => {
-            let cache: WeatherSummaryCache = serde_json::from_str(&data)?;
-            Ok(cache)
-        }
-        Err(_) => Ok(WeatherSummaryCache {
-            cache: std::collections::HashMap::new(),
-        }),
-    }
+    Ok(WeatherSummaryCache {
+        cache: store::load_namespace(WEATHER_SUMMARY_NAMESPACE).await?,
+    })
 }
 
-async fn save_weather_summary_cache(
-    cache: &WeatherSummaryCache,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // we should limit the cache size to 100 entries
-    let mut limited_cache = cache.cache.clone();
-    if limited_cache.len() > 100 {
-        let mut entries: Vec<(&String, &WeatherSummaryCacheItem)> = limited_cache.iter().collect();
-        entries.sort_by_key(|&(_, item)| item.time);
-        let keys_to_remove: Vec<String> = entries
-            .iter()
-            .take(limited_cache.len() - 100)
-            .map(|&(key, _)| key.clone())
-            .collect();
-        for key in keys_to_remove {
-            limited_cache.remove(&key);
-        }
+// Keeps only the newest MAX_SUMMARY_CACHE_ENTRIES summaries
+fn limit_weather_summary_cache(cache: &mut WeatherSummaryCache) {
+    if cache.cache.len() <= MAX_SUMMARY_CACHE_ENTRIES {
+        return;
     }
-    let data = serde_json::to_string(cache)?;
-    match tokio::fs::create_dir_all("cache").await {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to create cache directory: {}", e);
-            return Err(Box::new(e));
-        }
+    let mut entries: Vec<(&String, &WeatherSummaryCacheItem)> = cache.cache.iter().collect();
+    entries.sort_by_key(|&(_, item)| item.time);
+    let keys_to_remove: Vec<String> = entries
+        .iter()
+        .take(cache.cache.len() - MAX_SUMMARY_CACHE_ENTRIES)
+        .map(|&(key, _)| key.clone())
+        .collect();
+    for key in keys_to_remove {
+        cache.cache.remove(&key);
     }
-    tokio::fs::write(WEATHER_SUMMARY_CACHE_FILE, data).await?;
-    Ok(())
 }
 
-fn coord_key(coord: &Coordinates) -> String {
+async fn save_weather_summary_cache_entry(
+    key: &str,
+    entry: &WeatherSummaryCacheItem,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let expires_at = entry.time + chrono::Duration::seconds(CACHE_SECONDS);
+    store::upsert(
+        WEATHER_SUMMARY_NAMESPACE,
+        key,
+        entry,
+        Some(expires_at),
+        Some(MAX_SUMMARY_CACHE_ENTRIES),
+    )
+    .await
+}
+
+pub fn coord_key(coord: &Coordinates) -> String {
     format!("{:.4},{:.4}", coord.lat(), coord.lon())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct PublicWeatherResponse {
     #[serde(rename = "type")]
     pub response_type: String,
@@ -285,12 +313,100 @@ pub struct PublicWeatherResponse {
     pub properties: PublicProperties,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct PublicProperties {
     pub meta: ForecastMeta,
     pub timeseries: Vec<TimeSeriesEntry>,
 }
 
+async fn request_weather(
+    coord: &Coordinates,
+) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    let url = format!(
+        "https://api.met.no/weatherapi/locationforecast/2.0/complete?lat={}&lon={}",
+        coord.lat(),
+        coord.lon()
+    );
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&url)
+        .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+        .send_timed()
+        .await?
+        .json::<WeatherResponse>()
+        .await?;
+    Ok(response)
+}
+
+/// When the cached forecast for `coord` was fetched, if there is one.
+pub async fn weather_cached_at(coord: &Coordinates) -> Option<chrono::DateTime<chrono::Utc>> {
+    let cache_read = WEATHER_CACHE.read().await;
+    cache_read
+        .cache
+        .get(&coord_key(coord))
+        .map(|entry| entry.time)
+}
+
+/// Replaces the cached forecast under `key`, as if met.no had just sent it.
+pub async fn set_cached_weather(
+    key: &str,
+    weather: WeatherResponse,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let entry = WeatherCacheItem {
+        time: chrono::Utc::now(),
+        weather,
+    };
+    save_weather_cache_entry(key, &entry).await?;
+    WEATHER_CACHE
+        .write()
+        .await
+        .cache
+        .insert(key.to_string(), entry);
+    Ok(())
+}
+
+/// Drops the cached forecast under `key`; returns whether there was one.
+pub async fn forget_weather(key: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let removed = store::delete(WEATHER_NAMESPACE, key).await?;
+    WEATHER_CACHE.write().await.cache.remove(key);
+    Ok(removed)
+}
+
+/// Fetches a fresh forecast regardless of the cache. Unlike `fetch_weather`
+/// the cache is only locked once the response is in, so readers keep being
+/// served the old entry meanwhile.
+#[tracing::instrument(skip_all, fields(coord_key = %coord_key(coord)))]
+pub async fn refresh_weather(
+    coord: &Coordinates,
+) -> Result<PublicWeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    let response = request_weather(coord).await?;
+    let key = coord_key(coord);
+    let new_entry = WeatherCacheItem {
+        time: chrono::Utc::now(),
+        weather: response.clone(),
+    };
+
+    if let Err(e) = save_weather_cache_entry(&key, &new_entry).await {
+        tracing::warn!("Failed to save weather cache: {}", e);
+    }
+    let mut cache_write = WEATHER_CACHE.write().await;
+    cache_write.cache.insert(key, new_entry);
+    clear_useless_weather_cache(&mut cache_write).await?;
+    let weather = PublicWeatherResponse {
+        response_type: response.response_type,
+        geometry: response.geometry,
+        properties: PublicProperties {
+            meta: response.properties.meta,
+            timeseries: response.properties.timeseries,
+        },
+    };
+    events::publish(coord, EventKind::Weather, &weather);
+    Ok(weather)
+}
+
+#[tracing::instrument(skip_all, fields(coord_key = %coord_key(coord)))]
 pub async fn fetch_weather(
     coord: &Coordinates,
 ) -> Result<PublicWeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
@@ -298,7 +414,8 @@ pub async fn fetch_weather(
         let cache_read = WEATHER_CACHE.read().await;
         if let Some(entry) = cache_read.cache.get(&coord_key(coord)) {
             let current_time = chrono::Utc::now();
-            if current_time.signed_duration_since(entry.time).num_seconds() < 600 {
+            if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+                metrics::cache_lookup("weather", CacheResult::Hit);
                 return Ok(PublicWeatherResponse {
                     response_type: entry.weather.response_type.clone(),
                     geometry: entry.weather.geometry.clone(),
@@ -318,7 +435,7 @@ pub async fn fetch_weather(
                 *cache_write = file_cache;
             }
             Err(e) => {
-                eprintln!("Failed to load weather cache: {}", e);
+                tracing::warn!("Failed to load weather cache: {}", e);
             }
         }
     }
@@ -327,7 +444,8 @@ pub async fn fetch_weather(
 
     if let Some(entry) = cache_write.cache.get(&key) {
         let current_time = chrono::Utc::now();
-        if current_time.signed_duration_since(entry.time).num_seconds() < 600 {
+        if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+            metrics::cache_lookup("weather", CacheResult::Hit);
             return Ok(PublicWeatherResponse {
                 response_type: entry.weather.response_type.clone(),
                 geometry: entry.weather.geometry.clone(),
@@ -338,43 +456,255 @@ pub async fn fetch_weather(
             });
         }
     }
-    let url = format!(
-        "https://api.met.no/weatherapi/locationforecast/2.0/complete?lat={}&lon={}",
-        coord.lat(),
-        coord.lon()
-    );
-
-    let client = reqwest::Client::new();
-
-    let response = client
-        .get(&url)
-        .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
-        .send()
-        .await?
-        .json::<WeatherResponse>()
-        .await?;
+    let result = if cache_write.cache.contains_key(&key) {
+        CacheResult::Stale
+    } else {
+        CacheResult::Miss
+    };
+    metrics::cache_lookup("weather", result);
+    let response = request_weather(coord).await?;
 
     let new_entry = WeatherCacheItem {
         time: chrono::Utc::now(),
         weather: response.clone(),
     };
 
+    if let Err(e) = save_weather_cache_entry(&key, &new_entry).await {
+        tracing::warn!("Failed to save weather cache: {}", e);
+    }
     cache_write.cache.insert(key, new_entry);
     clear_useless_weather_cache(&mut cache_write).await?;
-    save_weather_cache(&cache_write).await?;
-    Ok(PublicWeatherResponse {
+    let weather = PublicWeatherResponse {
         response_type: response.response_type,
         geometry: response.geometry,
         properties: PublicProperties {
             meta: response.properties.meta,
             timeseries: response.properties.timeseries,
         },
+    };
+    events::publish(coord, EventKind::Weather, &weather);
+    Ok(weather)
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
+pub struct OceanInstant {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_surface_wave_from_direction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_surface_wave_height: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_water_speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_water_temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sea_water_to_direction: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanInstantDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<OceanInstant>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanTimeSeriesData {
+    pub instant: OceanInstantDetails,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanTimeSeriesEntry {
+    pub time: String,
+    pub data: OceanTimeSeriesData,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanMeta {
+    pub updated_at: String,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanProperties {
+    pub meta: OceanMeta,
+    pub timeseries: Vec<OceanTimeSeriesEntry>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct OceanForecast {
+    pub properties: OceanProperties,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OceanCacheItem {
+    pub time: chrono::DateTime<chrono::Utc>,
+    /// `None` when the location is outside the ocean model, which is worth
+    /// remembering as much as a forecast is
+    pub forecast: Option<OceanForecast>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OceanCache {
+    pub cache: std::collections::HashMap<String, OceanCacheItem>,
+}
+
+/// Sea conditions at one point in time. Wave height in m, directions in
+/// degrees, temperature in °C and current speed in m/s.
+#[derive(Serialize, ToSchema)]
+pub struct MarineConditions {
+    pub time: String,
+    #[serde(flatten)]
+    pub details: OceanInstant,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MarineForecast {
+    pub covered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    pub timeseries: Vec<MarineConditions>,
+}
+
+impl MarineForecast {
+    fn from_cached(forecast: &Option<OceanForecast>) -> Self {
+        match forecast {
+            Some(forecast) => MarineForecast {
+                covered: true,
+                reason: None,
+                updated_at: Some(forecast.properties.meta.updated_at.clone()),
+                timeseries: forecast
+                    .properties
+                    .timeseries
+                    .iter()
+                    .filter_map(|entry| {
+                        Some(MarineConditions {
+                            time: entry.time.clone(),
+                            details: entry.data.instant.details.clone()?,
+                        })
+                    })
+                    .collect(),
+            },
+            None => MarineForecast {
+                covered: false,
+                reason: Some(
+                    "Location is inland or outside the met.no ocean forecast coverage".to_string(),
+                ),
+                updated_at: None,
+                timeseries: Vec::new(),
+            },
+        }
+    }
+}
+
+async fn load_ocean_cache() -> Result<OceanCache, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(OceanCache {
+        cache: store::load_namespace(OCEAN_NAMESPACE).await?,
     })
 }
 
+async fn save_ocean_cache_entry(
+    key: &str,
+    entry: &OceanCacheItem,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let expires_at = entry.time + chrono::Duration::seconds(CACHE_SECONDS);
+    store::upsert(OCEAN_NAMESPACE, key, entry, Some(expires_at), None).await
+}
+
+/// When the cached ocean forecast for `coord` was fetched, if there is one.
+pub async fn ocean_cached_at(coord: &Coordinates) -> Option<chrono::DateTime<chrono::Utc>> {
+    let cache_read = OCEAN_CACHE.read().await;
+    cache_read
+        .cache
+        .get(&coord_key(coord))
+        .map(|entry| entry.time)
+}
+
+#[tracing::instrument(skip_all, fields(coord_key = %coord_key(coord)))]
+pub async fn fetch_ocean_forecast(
+    coord: &Coordinates,
+) -> Result<MarineForecast, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+
+    {
+        let cache_read = OCEAN_CACHE.read().await;
+        if let Some(entry) = cache_read.cache.get(&key) {
+            let current_time = chrono::Utc::now();
+            if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+                return Ok(MarineForecast::from_cached(&entry.forecast));
+            }
+        }
+    }
+    let mut cache_write = OCEAN_CACHE.write().await;
+
+    if cache_write.cache.is_empty() {
+        match load_ocean_cache().await {
+            Ok(file_cache) => {
+                *cache_write = file_cache;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load ocean cache: {}", e);
+            }
+        }
+    }
+
+    if let Some(entry) = cache_write.cache.get(&key) {
+        let current_time = chrono::Utc::now();
+        if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+            return Ok(MarineForecast::from_cached(&entry.forecast));
+        }
+    }
+
+    let url = format!(
+        "https://api.met.no/weatherapi/oceanforecast/2.0/complete?lat={}&lon={}",
+        coord.lat(),
+        coord.lon()
+    );
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&url)
+        .header("User-Agent", "weather for home (eemeliruoh@gmail.com)")
+        .send_timed()
+        .await?;
+
+    // met.no answers points on land or outside the model with 422 (or 400 for
+    // coordinates far outside the domain)
+    let forecast = match response.status() {
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY | reqwest::StatusCode::BAD_REQUEST => None,
+        _ => Some(response.error_for_status()?.json::<OceanForecast>().await?),
+    };
+
+    let marine = MarineForecast::from_cached(&forecast);
+    let new_entry = OceanCacheItem {
+        time: chrono::Utc::now(),
+        forecast,
+    };
+    if let Err(e) = save_ocean_cache_entry(&key, &new_entry).await {
+        tracing::warn!("Failed to save ocean cache: {}", e);
+    }
+    cache_write.cache.insert(key, new_entry);
+    let current_time = chrono::Utc::now();
+    cache_write.cache.retain(|_, entry| {
+        current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS
+    });
+    Ok(marine)
+}
+
 #[derive(Deserialize)]
 struct GenerateContentResponse {
     candidates: Vec<Candidate>,
+    #[serde(default, rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
 }
 
 #[derive(Deserialize)]
@@ -392,8 +722,45 @@ struct Part {
     text: String,
 }
 
+/// When the cached summary of this forecast was generated, if there is one.
+pub async fn summary_cached_at(
+    weather: &PublicWeatherResponse,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let key = format!(
+        "{:.4},{:.4}",
+        weather.geometry.coordinates[1], weather.geometry.coordinates[0]
+    );
+    let cache_read = WEATHER_SUMMARY_CACHE.read().await;
+    cache_read.cache.get(&key).map(|entry| entry.time)
+}
+
+/// Whether the forecast run or the alerts have changed since the cached
+/// summary of this forecast was written, or there is none.
+pub async fn summary_outdated(weather: &PublicWeatherResponse, alerts: &[WeatherAlert]) -> bool {
+    let key = format!(
+        "{:.4},{:.4}",
+        weather.geometry.coordinates[1], weather.geometry.coordinates[0]
+    );
+    let basis = SummaryBasis::new(weather, alerts);
+    let cache_read = WEATHER_SUMMARY_CACHE.read().await;
+    cache_read
+        .cache
+        .get(&key)
+        .is_none_or(|entry| entry.basis.as_ref() != Some(&basis))
+}
+
+/// Problems with the summary configuration, for the readiness check.
+pub fn config_problems() -> Vec<String> {
+    match std::env::var("GOOGLE_AISTUDIO_API_KEY") {
+        Ok(key) if !key.trim().is_empty() => Vec::new(),
+        _ => vec!["GOOGLE_AISTUDIO_API_KEY is not set, so summaries are unavailable".to_string()],
+    }
+}
+
+#[tracing::instrument(skip_all)]
 pub async fn summarize_weather(
     weather: &PublicWeatherResponse,
+    alerts: &[WeatherAlert],
     timezone: Option<String>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     // Create a cache key from the weather coordinates
@@ -407,7 +774,8 @@ pub async fn summarize_weather(
         if let Some(entry) = cache_read.cache.get(&key) {
             let current_time = chrono::Utc::now();
             // Cache summaries for 10 minutes (600 seconds)
-            if current_time.signed_duration_since(entry.time).num_seconds() < 600 {
+            if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+                metrics::cache_lookup("summary", CacheResult::Hit);
                 return Ok(entry.summary.clone());
             }
         }
@@ -422,7 +790,7 @@ pub async fn summarize_weather(
                 *summary_cache = file_cache;
             }
             Err(e) => {
-                eprintln!("Failed to load weather summary cache: {}", e);
+                tracing::warn!("Failed to load weather summary cache: {}", e);
             }
         }
     }
@@ -430,21 +798,144 @@ pub async fn summarize_weather(
     if let Some(entry) = summary_cache.cache.get(&key) {
         let current_time = chrono::Utc::now();
         // Cache summaries for 10 minutes (600 seconds)
-        if current_time.signed_duration_since(entry.time).num_seconds() < 600 {
+        if current_time.signed_duration_since(entry.time).num_seconds() < CACHE_SECONDS {
+            metrics::cache_lookup("summary", CacheResult::Hit);
             return Ok(entry.summary.clone());
         }
     }
+    let result = if summary_cache.cache.contains_key(&key) {
+        CacheResult::Stale
+    } else {
+        CacheResult::Miss
+    };
+    metrics::cache_lookup("summary", result);
+
+    if !ratelimit::spend_llm_call().await {
+        return Ok(fallback_summary(
+            summary_cache.cache.get(&key),
+            weather,
+            alerts,
+        ));
+    }
+    let summary = generate_summary(weather, alerts, timezone).await?;
+
+    // Save the summary to cache
+    let new_entry = WeatherSummaryCacheItem {
+        time: chrono::Utc::now(),
+        summary: summary.clone(),
+        basis: Some(SummaryBasis::new(weather, alerts)),
+    };
+
+    if let Err(e) = save_weather_summary_cache_entry(&key, &new_entry).await {
+        tracing::warn!("Failed to save weather summary cache: {}", e);
+    }
+    summary_cache.cache.insert(key, new_entry);
+    limit_weather_summary_cache(&mut summary_cache);
+
+    Ok(summary)
+}
+
+// Once the daily LLM budget is spent, the last summary is better than none
+// however old it is. Fallbacks aren't cached, so the model takes over again
+// as soon as there's budget.
+fn fallback_summary(
+    previous: Option<&WeatherSummaryCacheItem>,
+    weather: &PublicWeatherResponse,
+    alerts: &[WeatherAlert],
+) -> String {
+    match previous {
+        Some(entry) => entry.summary.clone(),
+        None => rule_based_summary(weather, alerts),
+    }
+}
+
+// "lightrainshowers_day" -> "Light rain showers"
+pub fn describe_symbol(symbol_code: &str) -> String {
+    let code = symbol_code.split('_').next().unwrap_or(symbol_code);
+    let description = match code {
+        "clearsky" => "clear skies".to_string(),
+        "fair" => "mostly clear skies".to_string(),
+        "partlycloudy" => "partly cloudy skies".to_string(),
+        "cloudy" => "cloudy skies".to_string(),
+        "fog" => "fog".to_string(),
+        _ => code
+            .replace("andthunder", " and thunder")
+            .replace("showers", " showers")
+            .replace("light", "light ")
+            .replace("heavy", "heavy "),
+    };
+    let mut chars = description.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// A plain summary from the forecast alone, for when the model can't be asked.
+/// Like the generated ones it leaves out the current temperature.
+pub fn rule_based_summary(weather: &PublicWeatherResponse, alerts: &[WeatherAlert]) -> String {
+    let mut sentences = Vec::new();
+    if let Some(alert) = alerts.first() {
+        sentences.push(format!(
+            "Warning: {}.",
+            alert.headline.as_deref().unwrap_or(&alert.event)
+        ));
+    }
+
+    let Some(current) = weather.properties.timeseries.first().map(|t| &t.data) else {
+        sentences.push("No forecast available.".to_string());
+        return sentences.join(" ");
+    };
+    if let Some(next) = current
+        .next_1_hours
+        .as_ref()
+        .or(current.next_6_hours.as_ref())
+    {
+        sentences.push(format!(
+            "{} over the next hours.",
+            describe_symbol(&next.summary.symbol_code)
+        ));
+    }
+    let precipitation = current
+        .next_6_hours
+        .as_ref()
+        .and_then(|f| f.details.as_ref())
+        .and_then(|d| d.precipitation_amount)
+        .unwrap_or(0.0);
+    if precipitation > 0.0 {
+        sentences.push(format!(
+            "About {:.1} mm of precipitation expected in the next six hours.",
+            precipitation
+        ));
+    }
+    if let Some(wind_speed) = current.instant.details.as_ref().and_then(|d| d.wind_speed) {
+        let wind = match wind_speed {
+            s if s < 1.5 => "Calm",
+            s if s < 5.5 => "Light",
+            s if s < 10.8 => "Moderate",
+            s if s < 20.8 => "Strong",
+            _ => "Extreme",
+        };
+        sentences.push(format!("{} wind.", wind));
+    }
+    sentences.join(" ")
+}
 
+async fn generate_summary(
+    weather: &PublicWeatherResponse,
+    alerts: &[WeatherAlert],
+    timezone: Option<String>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     // read from dotenv
-    let google_aistudio_api_key = dotenv::var("GOOGLE_AISTUDIO_API_KEY")?;
+    let google_aistudio_api_key = std::env::var("GOOGLE_AISTUDIO_API_KEY")?;
     let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemma-3-27b-it:generateContent?key={}",
-        google_aistudio_api_key
+        "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
+        SUMMARY_MODEL
     );
 
     let client = reqwest::Client::new();
 
-    let prompt = build_prompt(weather, timezone).await?;
+    let prompt = build_prompt(weather, alerts, timezone).await?;
 
     let request_body = serde_json::json!({
         "contents": [
@@ -465,9 +956,11 @@ pub async fn summarize_weather(
 
     let response = client
         .post(&url)
+        // In a header rather than the URL, so it can't end up in logs
+        .header("x-goog-api-key", google_aistudio_api_key)
         .header("Content-Type", "application/json")
         .json(&request_body)
-        .send()
+        .send_timed()
         .await?;
 
     if !response.status().is_success() {
@@ -476,6 +969,14 @@ pub async fn summarize_weather(
     }
 
     let parsed_response: GenerateContentResponse = response.json().await?;
+    if let Some(usage) = &parsed_response.usage_metadata {
+        metrics::llm_usage(
+            SUMMARY_MODEL,
+            usage.prompt_token_count,
+            usage.candidates_token_count,
+        );
+        ratelimit::record_llm_tokens(usage.prompt_token_count + usage.candidates_token_count).await;
+    }
 
     let summary = parsed_response
         .candidates
@@ -487,21 +988,68 @@ pub async fn summarize_weather(
         .ok_or("No parts in content")?
         .text
         .clone();
+    Ok(summary)
+}
 
-    // Save the summary to cache
+/// Replaces the cached summary under `key` with a hand-written one.
+pub async fn set_cached_summary(
+    key: &str,
+    summary: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let entry = WeatherSummaryCacheItem {
+        time: chrono::Utc::now(),
+        summary,
+        basis: None,
+    };
+    save_weather_summary_cache_entry(key, &entry).await?;
+    let mut summary_cache = WEATHER_SUMMARY_CACHE.write().await;
+    summary_cache.cache.insert(key.to_string(), entry);
+    limit_weather_summary_cache(&mut summary_cache);
+    Ok(())
+}
+
+/// Drops the cached summary under `key`; returns whether there was one.
+pub async fn forget_summary(key: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let removed = store::delete(WEATHER_SUMMARY_NAMESPACE, key).await?;
+    WEATHER_SUMMARY_CACHE.write().await.cache.remove(key);
+    Ok(removed)
+}
+
+/// Generates a new summary regardless of the cache, without blocking readers
+/// of the old one while the model is busy.
+#[tracing::instrument(skip_all)]
+pub async fn refresh_summary(
+    weather: &PublicWeatherResponse,
+    alerts: &[WeatherAlert],
+    timezone: Option<String>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let key = format!(
+        "{:.4},{:.4}",
+        weather.geometry.coordinates[1], weather.geometry.coordinates[0]
+    );
+    if !ratelimit::spend_llm_call().await {
+        let previous = WEATHER_SUMMARY_CACHE.read().await.cache.get(&key).cloned();
+        return Ok(fallback_summary(previous.as_ref(), weather, alerts));
+    }
+    let summary = generate_summary(weather, alerts, timezone).await?;
     let new_entry = WeatherSummaryCacheItem {
         time: chrono::Utc::now(),
         summary: summary.clone(),
+        basis: Some(SummaryBasis::new(weather, alerts)),
     };
 
+    if let Err(e) = save_weather_summary_cache_entry(&key, &new_entry).await {
+        tracing::warn!("Failed to save weather summary cache: {}", e);
+    }
+    let mut summary_cache = WEATHER_SUMMARY_CACHE.write().await;
     summary_cache.cache.insert(key, new_entry);
-    save_weather_summary_cache(&summary_cache).await?;
-
+    limit_weather_summary_cache(&mut summary_cache);
     Ok(summary)
 }
 
 async fn build_prompt(
     weather: &PublicWeatherResponse,
+    alerts: &[WeatherAlert],
     timezone: Option<String>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let now = if let Some(timezone) = timezone {
@@ -513,10 +1061,25 @@ async fn build_prompt(
 
     let time_str = now.format("%H:%M").to_string();
 
+    let coords = Coordinates::new(
+        weather.geometry.coordinates[1],
+        weather.geometry.coordinates[0],
+    );
+    let sun = astronomy::sun_report(&coords, now.date_naive(), &now.timezone());
+    let daylight_str = match (sun.sunrise, sun.sunset, sun.polar) {
+        (Some(sunrise), Some(sunset), _) => format!(
+            "Sunrise: {}\nSunset: {}",
+            sunrise.format("%H:%M"),
+            sunset.format("%H:%M")
+        ),
+        (_, _, Some(PolarCondition::PolarDay)) => "The sun does not set today.".to_string(),
+        _ => "The sun does not rise today.".to_string(),
+    };
+
     let current = &weather
         .properties
         .timeseries
-        .get(0)
+        .first()
         .ok_or("No timeseries data available")?
         .data;
 
@@ -580,9 +1143,39 @@ async fn build_prompt(
     let next12_precip = fmt(next_12h.and_then(|d| d.precipitation_amount));
     let next12_prob = fmt(next_12h.and_then(|d| d.probability_of_precipitation));
 
+    let uv_str = uv::prompt_line(weather, &now.timezone())
+        .map(|line| format!("{}\n\n", line))
+        .unwrap_or_default();
+
+    let alerts_str = if alerts.is_empty() {
+        String::new()
+    } else {
+        let lines: Vec<String> = alerts
+            .iter()
+            .map(|alert| {
+                format!(
+                    "- {} ({:?} severity, {:?}){}",
+                    alert.headline.as_deref().unwrap_or(&alert.event),
+                    alert.severity,
+                    alert.certainty,
+                    alert
+                        .instruction
+                        .as_ref()
+                        .map(|i| format!(": {}", i))
+                        .unwrap_or_default()
+                )
+            })
+            .collect();
+        format!(
+            "Active official weather warnings:\n{}\n\n",
+            lines.join("\n")
+        )
+    };
+
     let prompt = format!(
-        "Generate a concise, natural weather description for a dashboard. Keep it under 25 words.\n\nCurrent time: {}\n\nCurrent conditions:\nTemperature: {}°C\nWind: {} m/s with gusts of {} m/s\nHumidity: {}%\nCloud area fraction: {}%\nFog area fraction: {}%\n\nForecast 1 hour:\nSummary: {}\nPrecipitation: {} mm with a probability of {}%\n\nForecast 6 hours:\nSummary: {}\nMax Temperature: {}°C\nMin Temperature: {}°C\nPrecipitation: {} mm with a probability of {}%\n\nForecast 12 hours:\nSummary: {}\nMax Temperature: {}°C\nMin Temperature: {}°C\nPrecipitation: {} mm with a probability of {}%\n\nRequirements:\n- Be conversational and friendly.\n- Do not mention the current temperature. It will be displayed seperately.\n- Upcoming temperatures should be included if there is a significant change.\n- For wind: Use descriptive terms (calm, light, moderate, strong, extreme) - NEVER use specific values.\n- Use natural language, no technical jargon.\n- NO EMOJIS.\n\nGenerate description:",
+        "Generate a concise, natural weather description for a dashboard. Keep it under 25 words.\n\nCurrent time: {}\n{}\n\nCurrent conditions:\nTemperature: {}°C\nWind: {} m/s with gusts of {} m/s\nHumidity: {}%\nCloud area fraction: {}%\nFog area fraction: {}%\n\nForecast 1 hour:\nSummary: {}\nPrecipitation: {} mm with a probability of {}%\n\nForecast 6 hours:\nSummary: {}\nMax Temperature: {}°C\nMin Temperature: {}°C\nPrecipitation: {} mm with a probability of {}%\n\nForecast 12 hours:\nSummary: {}\nMax Temperature: {}°C\nMin Temperature: {}°C\nPrecipitation: {} mm with a probability of {}%\n\n{}{}Requirements:\n- Be conversational and friendly.\n- If there are active official weather warnings, always mention the most severe one first.\n- Do not mention the current temperature. It will be displayed seperately.\n- Upcoming temperatures should be included if there is a significant change.\n- Use sunrise and sunset to say whether upcoming weather falls in the afternoon, evening or night.\n- Only mention UV or sun protection if a UV index is given above.\n- For wind: Use descriptive terms (calm, light, moderate, strong, extreme) - NEVER use specific values.\n- Use natural language, no technical jargon.\n- NO EMOJIS.\n\nGenerate description:",
         time_str,
+        daylight_str,
         temp_str,
         wind_str,
         gust_str,
@@ -602,6 +1195,8 @@ async fn build_prompt(
         next12_min,
         next12_precip,
         next12_prob,
+        uv_str,
+        alerts_str,
     );
 
     Ok(prompt)
@@ -0,0 +1,55 @@
+import { PUBLIC_BACKEND_URL } from "$env/static/public";
+
+// An empty PUBLIC_BACKEND_URL means the backend serves this app itself
+export const BACKEND_URL = PUBLIC_BACKEND_URL ?? "http://localhost:3000";
+
+export type Units = "metric" | "imperial";
+
+export type Widget = {
+	id: string;
+	type: string;
+	x: number;
+	y: number;
+	w: number;
+	h: number;
+	// Checked by the backend against the widget's settings, so they can go
+	// straight to the component as props
+	// biome-ignore lint/suspicious/noExplicitAny: props differ per widget
+	settings: Record<string, any>;
+};
+
+export type Dashboard = {
+	name: string;
+	title: string | null;
+	columns: number;
+	widgets: Widget[];
+};
+
+// What the page showed before dashboards lived on the backend, for when it
+// can't be reached
+export const fallbackDashboard: Dashboard = {
+	name: "default",
+	title: null,
+	columns: 2,
+	widgets: [
+		{ id: "weather", type: "weather", x: 0, y: 0, w: 1, h: 2, settings: {} },
+		{ id: "clock", type: "clock", x: 1, y: 0, w: 1, h: 1, settings: {} },
+		{ id: "t3", type: "t3", x: 1, y: 1, w: 1, h: 1, settings: {} },
+	],
+};
+
+export async function loadDashboard(name: string): Promise<Dashboard> {
+	const response = await fetch(
+		`${BACKEND_URL}/api/v1/dashboards/${encodeURIComponent(name)}`
+	);
+	if (!response.ok) {
+		throw new Error(`HTTP error! status: ${response.status}`);
+	}
+	return response.json();
+}
+
+export function formatTemperature(celsius: number, units?: Units) {
+	return units === "imperial"
+		? `${Math.round((celsius * 9) / 5 + 32)}°F`
+		: `${celsius}°C`;
+}
@@ -0,0 +1,4 @@
+// Everything is fetched in the browser, so the app is a single prerendered
+// shell that the backend can serve as static files
+export const ssr = false;
+export const prerender = true;